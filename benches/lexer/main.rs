@@ -0,0 +1,106 @@
+//! Compares the lexer against the previous implementation, which allocated a `String` for
+//! every token, on a generated multi-megabyte Lox source.
+//!
+//! Run with `cargo bench --bench lexer`.
+
+mod old_lexer;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use old_lexer::OldLexer;
+use rustlox::lexing::lexer::Lexer;
+
+/// Forwards to the system allocator while counting allocations.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const TARGET_SIZE: usize = 8 * 1024 * 1024;
+const RUNS: usize = 5;
+
+fn generate_source() -> String {
+    let mut src = String::with_capacity(TARGET_SIZE + 256);
+    let mut i = 0;
+    while src.len() < TARGET_SIZE {
+        src.push_str(&format!(
+            "/* comment {i} */\nvar name{i} = (counter{m} + 0x{i:x}) * 1_000 / 6.02e23 >= -{i}.5;\n\
+             /* block /* nested */ comment */ print \"string number {i}\" != nil and !true;\n",
+            m = i % 97,
+        ));
+        i += 1;
+    }
+    src
+}
+
+/// Lexes the source `RUNS` times, returning the best time, the number of tokens and the
+/// number of allocations made by one run.
+fn measure(mut lex_all: impl FnMut() -> usize) -> (Duration, usize, usize) {
+    let mut best = Duration::MAX;
+    let mut tokens = 0;
+    let mut allocations = 0;
+    for _ in 0..RUNS {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        tokens = lex_all();
+        best = best.min(start.elapsed());
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    (best, tokens, allocations)
+}
+
+fn report(name: &str, size: usize, (time, tokens, allocations): (Duration, usize, usize)) {
+    println!(
+        "{:<12} {:>9.2?} {:>8.1} MB/s {:>9} tokens {:>9} allocations ({:.3} per token)",
+        name,
+        time,
+        size as f64 / (1024.0 * 1024.0) / time.as_secs_f64(),
+        tokens,
+        allocations,
+        allocations as f64 / tokens as f64,
+    );
+}
+
+fn main() {
+    let src = generate_source();
+    println!("Lexing {} bytes, best of {} runs", src.len(), RUNS);
+
+    let old = measure(|| {
+        let mut lex = OldLexer::new(&src);
+        let mut tokens = 0;
+        while let Some(tok) = lex.scan_token() {
+            std::hint::black_box(tok.ok());
+            tokens += 1;
+        }
+        tokens
+    });
+    report("old lexer", src.len(), old);
+
+    let new = measure(|| {
+        let mut lex = Lexer::new(&src);
+        let mut tokens = 0;
+        while let Some(tok) = lex.scan_token() {
+            std::hint::black_box(tok.ok());
+            tokens += 1;
+        }
+        tokens
+    });
+    report("new lexer", src.len(), new);
+}
@@ -0,0 +1,295 @@
+use std::str::Chars;
+
+use peekmore::{PeekMore, PeekMoreIterator};
+
+use rustlox::{
+    error::LexingError,
+    lexing::{
+        lexer::Lexer,
+        token::{TokenType, TokenType::*},
+    },
+    position::Position,
+};
+
+/// Token as produced before tokens borrowed from the source: every token owns its text.
+#[allow(dead_code)]
+pub struct OldToken {
+    pub typ: TokenType,
+    pub lexeme: String,
+    pub from_pos: Position,
+    pub to_pos: Position,
+}
+
+/// Copy of the lexer before tokens borrowed from the source, kept as a baseline.
+pub struct OldLexer<'a> {
+    pub source: PeekMoreIterator<Chars<'a>>,
+    pub position: Position,
+    /// Source text of the token being scanned
+    text: String,
+}
+
+impl<'a> OldLexer<'a> {
+    pub fn new(source: &'a str) -> OldLexer<'a> {
+        OldLexer {
+            source: source.chars().peekmore(),
+            position: Position::default(),
+            text: String::new(),
+        }
+    }
+
+    /// Gets a character from the source and returns it. Returns None iff the end has been reached.
+    ///
+    /// The source is updated so that the subsequent calls return the next characters, and the
+    /// character is appended to the text of the current token.
+    fn next(&mut self) -> Option<char> {
+        let char = self.source.next()?;
+        self.text.push(char);
+        if char == '\n' {
+            self.position = self.position.brk()
+        } else {
+            self.position = self.position.bump(1)
+        }
+        Some(char)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.source.peek().copied()
+    }
+
+    fn match_peek(&mut self, expected: char) -> bool {
+        let matching = self.peek() == Some(expected);
+        if matching {
+            let _ = self.next(); //force consumption if match
+        }
+        matching
+    }
+
+    /// Scans the next token from the source. Returns None iff the end has been reached.
+    pub fn scan_token(&mut self) -> Option<Result<OldToken, LexingError>> {
+        let init_pos = self.position;
+        self.text.clear();
+
+        let char = self.next()?;
+
+        let scanned = match char {
+            '(' => Ok(LEFTPAREN),
+            ')' => Ok(RIGHTPAREN),
+            '{' => Ok(LEFTBRACE),
+            '}' => Ok(RIGHTBRACE),
+            ',' => Ok(COMMA),
+            '.' => Ok(DOT),
+            '+' => Ok(PLUS),
+            '-' => Ok(MINUS),
+            ';' => Ok(SEMICOLON),
+            '*' => Ok(STAR),
+            '!' => {
+                if self.match_peek('=') {
+                    Ok(BANGEQUAL)
+                } else {
+                    Ok(BANG)
+                }
+            }
+            '=' => {
+                if self.match_peek('=') {
+                    Ok(EQUALEQUAL)
+                } else {
+                    Ok(EQUAL)
+                }
+            }
+            '<' => {
+                if self.match_peek('=') {
+                    Ok(LESSEQUAL)
+                } else {
+                    Ok(LESS)
+                }
+            }
+            '>' => {
+                if self.match_peek('=') {
+                    Ok(GREATEREQUAL)
+                } else {
+                    Ok(GREATER)
+                }
+            }
+
+            // A '/' is either a comment or a division, so we need to peek on the next char
+            '/' => {
+                if self.match_peek('/') {
+                    // A comment goes until the end of the line.
+                    let mut next = self.peek();
+                    while next != Some('\n') && next.is_some() {
+                        let _ = self.next();
+                        next = self.peek();
+                    }
+                    Ok(WHITESPACE)
+                } else if self.match_peek('*') {
+                    self.block_comment(init_pos)
+                } else {
+                    Ok(SLASH)
+                }
+            }
+
+            // handle all whitespace recognized by Rust and ignore it
+            x if x.is_whitespace() => {
+                let mut next = self.peek();
+                while next.is_some() && next.unwrap().is_whitespace() {
+                    let _ = self.next();
+                    next = self.peek();
+                }
+                Ok(WHITESPACE)
+            }
+
+            // Numbers, see `Lexer::number` for the accepted forms
+            x if x.is_ascii_digit() => self.number(x, init_pos),
+
+            // words, which may be keywords or identifiers
+            x if x.is_ascii_alphabetic() => {
+                let mut next = self.peek();
+                while next.is_some() && next.unwrap().is_ascii_alphabetic() {
+                    let _ = self.next();
+                    next = self.peek();
+                }
+                Ok(Lexer::identify_keyword(&self.text).unwrap_or(IDENTIFIER))
+            }
+
+            // string literals, parse until closing
+            '"' => {
+                let mut next = self.peek();
+                while next != Some('"') && next.is_some() {
+                    let _ = self.next();
+                    next = self.peek();
+                }
+                if next.is_some() {
+                    let _ = self.next();
+                    Ok(STRING)
+                } else {
+                    Err(LexingError::UnterminatedString { start: init_pos })
+                }
+            }
+
+            x => Err(LexingError::UnknownCharacter {
+                pos: init_pos,
+                char: x,
+            }),
+        };
+
+        Some(scanned.map(|typ| OldToken {
+            typ,
+            lexeme: self.text.clone(),
+            from_pos: init_pos,
+            to_pos: self.position,
+        }))
+    }
+
+    /// Skips the rest of a block comment whose opening `/*` has already been consumed.
+    ///
+    /// Block comments nest, so `/* a /* b */ c */` is a single comment. Reaching the end of
+    /// the source before every opened comment is closed is reported at the outermost `/*`.
+    fn block_comment(&mut self, init_pos: Position) -> Result<TokenType, LexingError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some('/') if self.match_peek('*') => depth += 1,
+                Some('*') if self.match_peek('/') => depth -= 1,
+                Some(_) => (),
+                None => return Err(LexingError::UnterminatedBlockComment { start: init_pos }),
+            }
+        }
+        Ok(WHITESPACE)
+    }
+
+    /// Scans the rest of a number literal whose first digit has already been consumed.
+    ///
+    /// Accepted forms are decimal numbers with an optional fractional part and exponent
+    /// (`12`, `1.5`, `6.02e23`, `1E-9`), and integers in hexadecimal (`0x1F`), binary (`0b101`)
+    /// or octal (`0o17`). Digits may be separated by single underscores (`1_000_000`).
+    /// '123.blabla' is still parsed as 123, followed by a dot, followed by blabla.
+    fn number(&mut self, first: char, init_pos: Position) -> Result<TokenType, LexingError> {
+        let radix = match (first, self.peek()) {
+            ('0', Some('x' | 'X')) => 16,
+            ('0', Some('b' | 'B')) => 2,
+            ('0', Some('o' | 'O')) => 8,
+            _ => 10,
+        };
+
+        let result = if radix != 10 {
+            let _ = self.next();
+            self.digits(radix, false)
+        } else {
+            self.digits(10, true).and_then(|()| {
+                if self.peek() == Some('.')
+                    && self
+                        .source
+                        .advance_cursor()
+                        .peek()
+                        .is_some_and(|c| c.is_ascii_digit())
+                {
+                    self.source.reset_cursor();
+                    let _ = self.next();
+                    self.digits(10, false)?;
+                } else {
+                    self.source.reset_cursor();
+                }
+                if self.peek().is_some_and(|c| c == 'e' || c == 'E') {
+                    let _ = self.next();
+                    if self.peek().is_some_and(|c| c == '+' || c == '-') {
+                        let _ = self.next();
+                    }
+                    self.digits(10, false)?;
+                }
+                Ok(())
+            })
+        };
+
+        // a number glued to letters or digits of the wrong base is malformed as a whole
+        let result = result.and_then(|()| match self.peek() {
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' => {
+                Err(format!("unexpected character '{}' in number literal", c))
+            }
+            _ => Ok(()),
+        });
+
+        match result {
+            Ok(()) => Ok(NUMBER),
+            Err(reason) => {
+                // skip the rest of the malformed literal so lexing resumes on a clean boundary
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    let _ = self.next();
+                }
+                Err(LexingError::MalformedNumber {
+                    start: init_pos,
+                    end: self.position,
+                    reason,
+                })
+            }
+        }
+    }
+
+    /// Consumes a run of digits in the given radix, allowing single underscores between digits.
+    /// If `started` is true, a digit has already been consumed just before.
+    fn digits(&mut self, radix: u32, started: bool) -> Result<(), String> {
+        let mut last_was_digit = started;
+        let mut any_digit = started;
+        loop {
+            match self.peek() {
+                Some(c) if c.is_digit(radix) => {
+                    last_was_digit = true;
+                    any_digit = true;
+                }
+                Some('_') if last_was_digit => last_was_digit = false,
+                Some('_') => return Err("digit separators must sit between two digits".to_string()),
+                _ => break,
+            }
+            let _ = self.next();
+        }
+        if !any_digit {
+            Err("expected digits".to_string())
+        } else if !last_was_digit {
+            Err("number literal cannot end with a digit separator".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
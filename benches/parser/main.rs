@@ -0,0 +1,158 @@
+//! Measures the time and memory the parser takes on a generated multi-megabyte Lox expression.
+//!
+//! The parser used to box every node of the tree. That version is kept at revision 4cc22bf,
+//! whose copy of this bench measures both: check it out and run the bench there to compare.
+//!
+//! Run with `cargo bench --bench parser`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use rustlox::{
+    lexing::{
+        lexer::Lexer,
+        token::{Token, TokenType},
+    },
+    parsing::{expr::Ast, parser::Parser},
+};
+
+/// Forwards to the system allocator while counting allocations and live bytes. Reallocations
+/// are forwarded too, as the system allocator can often resize in place, so a vector growing or
+/// shrinking counts as one allocation whose size changes rather than a copy.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        let live = LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        if new_size >= layout.size() {
+            let grown = new_size - layout.size();
+            let live = LIVE_BYTES.fetch_add(grown, Ordering::Relaxed) + grown;
+            PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+        } else {
+            LIVE_BYTES.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const TARGET_SIZE: usize = 8 * 1024 * 1024;
+const RUNS: usize = 5;
+/// Number of subexpressions in each parenthesized group of the generated source
+const GROUP: usize = 64;
+
+/// Generates a single expression of comma-separated groups, nested a few levels deep so that
+/// the tree stays shallow enough to be dropped recursively.
+fn generate_source() -> String {
+    let mut groups: Vec<String> = Vec::new();
+    let mut size = 0;
+    let mut i = 0;
+    while size < TARGET_SIZE {
+        let term = format!(
+            "(0x{i:x} + 2.5 * -{m} >= 7 ~/ 2 ** 3 ? \"string {i}\" : nil == !true)",
+            m = i % 97,
+        );
+        size += term.len() + 2;
+        groups.push(term);
+        i += 1;
+    }
+    while groups.len() > 1 {
+        groups = groups
+            .chunks(GROUP)
+            .map(|chunk| format!("({})", chunk.join(", ")))
+            .collect();
+    }
+    groups.pop().unwrap_or_default()
+}
+
+/// What parsing costs: the best time, the number of nodes, and during one run the number
+/// of allocations, the peak of live bytes and the bytes still held by the finished tree.
+struct Measure {
+    time: Duration,
+    nodes: usize,
+    allocations: usize,
+    peak: usize,
+    retained: usize,
+}
+
+/// Parses the tokens `RUNS` times, dropping the tree each time once its nodes are counted.
+fn measure<T>(mut parse: impl FnMut() -> T, count: impl Fn(&T) -> usize) -> Measure {
+    let mut best = Duration::MAX;
+    let mut measure = None;
+    for _ in 0..RUNS {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let live_before = LIVE_BYTES.load(Ordering::Relaxed);
+        PEAK_BYTES.store(live_before, Ordering::Relaxed);
+        let start = Instant::now();
+        let tree = parse();
+        best = best.min(start.elapsed());
+        measure = Some(Measure {
+            time: best,
+            nodes: count(&tree),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed) - before,
+            peak: PEAK_BYTES.load(Ordering::Relaxed) - live_before,
+            retained: LIVE_BYTES.load(Ordering::Relaxed) - live_before,
+        });
+    }
+    measure.expect("at least one run")
+}
+
+fn report(name: &str, size: usize, measure: Measure) {
+    let mb = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<12} {:>9.2?} {:>8.1} MB/s {:>9} nodes {:>9} allocations {:>8.1} MB peak {:>8.1} MB retained ({:.1} bytes per node)",
+        name,
+        measure.time,
+        mb(size) / measure.time.as_secs_f64(),
+        measure.nodes,
+        measure.allocations,
+        mb(measure.peak),
+        mb(measure.retained),
+        measure.retained as f64 / measure.nodes as f64,
+    );
+}
+
+fn main() {
+    let src = generate_source();
+    let tokens: Vec<Token> = Lexer::new(&src)
+        .map(|tok| tok.expect("generated source should lex"))
+        .filter(|tok| tok.typ != TokenType::WHITESPACE)
+        .collect();
+    println!(
+        "Parsing {} bytes ({} tokens), best of {} runs",
+        src.len(),
+        tokens.len(),
+        RUNS
+    );
+
+    let arena = measure(
+        || {
+            Parser::new(tokens.iter().cloned())
+                .parse()
+                .expect("generated source should parse")
+        },
+        Ast::len,
+    );
+    report("arena AST", src.len(), arena);
+}
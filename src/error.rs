@@ -1,14 +1,61 @@
 use crate::position::Position;
 
-pub enum LexingError {}
+#[derive(Debug, PartialEq)]
+pub enum LexingError {
+    UnterminatedString { start: Position },
+    UnterminatedBlockComment { start: Position },
+    UnknownCharacter { pos: Position, char: char },
+    MalformedNumber { start: Position, end: Position, reason: String },
+    InvalidUtf8 { pos: Position },
+    ReadFailed { pos: Position, msg: String },
+}
 
+#[derive(Debug, PartialEq)]
 pub enum ParsingError {
-    EarlyEof { pos: Position },
+    EarlyEof { pos: Position, expected: String },
     UnclosedParenthesis { open_paren_start: Position },
-    IllegalToken { start : Position, end : Position, expected : String }
+    UnfinishedConditional { question_mark: Position },
+    IllegalToken { start : Position, end : Position, expected : String },
+    NumberOutOfRange { start : Position, end : Position },
+    MissingLeftOperand { start : Position, end : Position, operator : String },
+    InvalidAssignmentTarget { start : Position, end : Position },
+    /// `.name` without a call, as values have no fields
+    FieldAccess { start : Position, end : Position, name : String },
+    OutsideLoop { start : Position, end : Position, keyword : String },
+    /// `return` in the statements of the program rather than in a function
+    OutsideFunction { start : Position, end : Position },
+}
+
+/// Problems that do not prevent the program from running
+pub enum ParsingWarning {
+    UnreachableArm { start : Position, end : Position },
+}
+
+/// Why a source could not be turned into a tree, and so neither run nor formatted
+#[derive(Debug, PartialEq)]
+pub enum SyntaxError {
+    Lexing(Vec<LexingError>),
+    Parsing(Vec<ParsingError>),
 }
 
 #[derive(Debug)]
 pub struct LoxError {
     pub msg: String,
+    /// Span of the expression that failed, once known
+    pub span: Option<(Position, Position)>,
+    /// Functions written in Lox that the error went out of, innermost first, each with where
+    /// it was called
+    pub trace: Vec<(String, Position)>,
+}
+
+impl LoxError {
+    pub fn new(msg : impl Into<String>) -> LoxError {
+        LoxError { msg: msg.into(), span: None, trace: Vec::new() }
+    }
+
+    /// Locates the error at the given span, unless it was already located in a subexpression
+    pub fn at(mut self, start : Position, end : Position) -> LoxError {
+        self.span.get_or_insert((start, end));
+        self
+    }
 }
@@ -0,0 +1,172 @@
+/// Layout of a piece of source, to be printed within a maximum width.
+///
+/// A group is printed on one line when it fits, and otherwise breaks all of its own lines, the
+/// groups inside it deciding again for themselves.
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// A space, or a line break when the group is broken
+    Line,
+    /// Nothing, or a line break when the group is broken
+    SoftLine,
+    /// A line break, which breaks every enclosing group
+    HardLine,
+    /// A line break unless the line is still empty, which breaks every enclosing group
+    FreshLine,
+    /// Text only printed when the group is broken, like a trailing comma
+    IfBreak(&'static str),
+    /// Text moved to the end of the line, for comments following code. Code printed after it
+    /// goes on a new line, and groups that would put code after it do not fit.
+    LineSuffix(String),
+    /// Lines within are indented one level deeper
+    Indent(Vec<Doc>),
+    Group(Vec<Doc>),
+}
+
+pub fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+const INDENT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Prints the documents, breaking the groups that do not fit within `width` columns. Lines
+/// have no trailing whitespace, and the output ends with a line break.
+pub fn print(docs: &[Doc], width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    let mut suffixes: Vec<&str> = Vec::new();
+    // documents left to print, the next one last
+    let mut stack: Vec<(usize, Mode, &Doc)> =
+        docs.iter().rev().map(|doc| (0, Mode::Break, doc)).collect();
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) => {
+                let mut s = s.as_str();
+                if !suffixes.is_empty() && !s.trim().is_empty() {
+                    newline(&mut out, &mut suffixes, indent);
+                    col = indent;
+                    // the space separating it from the code before is not needed anymore
+                    s = s.trim_start();
+                }
+                out.push_str(s);
+                col = match s.rfind('\n') {
+                    Some(i) => s[i + 1..].chars().count(),
+                    None => col + s.chars().count(),
+                };
+            }
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if let Doc::Line = doc {
+                    out.push(' ');
+                    col += 1;
+                }
+            }
+            Doc::FreshLine if out.rsplit('\n').next().unwrap_or("").trim().is_empty() => (),
+            Doc::Line | Doc::SoftLine | Doc::HardLine | Doc::FreshLine => {
+                newline(&mut out, &mut suffixes, indent);
+                col = indent;
+            }
+            Doc::IfBreak(s) => {
+                if mode == Mode::Break {
+                    out.push_str(s);
+                    col += s.chars().count();
+                }
+            }
+            Doc::LineSuffix(s) => suffixes.push(s),
+            Doc::Indent(docs) => {
+                stack.extend(docs.iter().rev().map(|doc| (indent + INDENT, mode, doc)));
+            }
+            Doc::Group(docs) => {
+                let mode = match mode {
+                    Mode::Break
+                        if !fits(
+                            docs,
+                            &stack,
+                            width.saturating_sub(col),
+                            !suffixes.is_empty(),
+                        ) =>
+                    {
+                        Mode::Break
+                    }
+                    _ => Mode::Flat,
+                };
+                stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc)));
+            }
+        }
+    }
+    newline(&mut out, &mut suffixes, 0);
+    out
+}
+
+fn newline(out: &mut String, suffixes: &mut Vec<&str>, indent: usize) {
+    for suffix in suffixes.drain(..) {
+        out.push_str(suffix);
+    }
+    out.truncate(out.trim_end_matches(' ').len());
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+}
+
+/// Whether the group can be printed flat in the remaining width, along with what follows it up
+/// to the next line break. With `suffix`, a comment is already waiting for the end of the line.
+fn fits(
+    group: &[Doc],
+    rest: &[(usize, Mode, &Doc)],
+    mut remaining: usize,
+    mut suffix: bool,
+) -> bool {
+    // the documents of the group itself are marked, the ones following it are not
+    let mut stack: Vec<(bool, Mode, &Doc)> = group
+        .iter()
+        .rev()
+        .map(|doc| (true, Mode::Flat, doc))
+        .collect();
+    let mut rest = rest.iter().rev();
+    loop {
+        let (in_group, mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (false, *mode, *doc),
+                None => return true,
+            },
+        };
+        let width = match doc {
+            Doc::Text(s) if suffix && !s.trim().is_empty() => return !in_group,
+            // text over several lines ends the line being measured
+            Doc::Text(s) | Doc::LineSuffix(s) if s.contains('\n') => {
+                return !in_group && s.lines().next().unwrap_or("").chars().count() <= remaining;
+            }
+            Doc::Text(s) => s.chars().count(),
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+            Doc::HardLine | Doc::FreshLine => return !in_group,
+            Doc::Line => 1,
+            Doc::SoftLine => 0,
+            Doc::IfBreak(s) if mode == Mode::Break => s.chars().count(),
+            Doc::IfBreak(_) => 0,
+            // counted so that lines fit along with their comments
+            Doc::LineSuffix(s) => {
+                suffix = true;
+                s.chars().count()
+            }
+            Doc::Indent(docs) => {
+                stack.extend(docs.iter().rev().map(|doc| (in_group, mode, doc)));
+                0
+            }
+            // groups following this one may still break, which ends the line at their first
+            // line break
+            Doc::Group(docs) => {
+                stack.extend(docs.iter().rev().map(|doc| (in_group, mode, doc)));
+                0
+            }
+        };
+        remaining = match remaining.checked_sub(width) {
+            Some(remaining) => remaining,
+            None => return false,
+        };
+    }
+}
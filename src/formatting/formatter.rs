@@ -0,0 +1,891 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+};
+
+use crate::{
+    error::SyntaxError,
+    formatting::doc::{self, text, Doc},
+    lexing::{
+        lexer::Lexer,
+        token::{Token, TokenType, TriviaKind},
+    },
+    parsing::{
+        expr::{AssignOp, Ast, BinaryOp, Call, Expr, Function, MatchArm, NodeId, Pattern, Var},
+        operators::{self, Assoc, Operator},
+        parser::Parser,
+        visit::{walk_expr, Visitor},
+    },
+    position::Position,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatOptions {
+    /// Column after which lines are wrapped, when they can be
+    pub width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions { width: 80 }
+    }
+}
+
+/// Reprints a program in the canonical layout, keeping its comments, its parentheses and the
+/// spelling of its literals. Formatting the output again gives it back unchanged.
+pub fn format(src: &str, options: &FormatOptions) -> Result<String, SyntaxError> {
+    let tokens = Lexer::new(src)
+        .scan_lossless()
+        .map_err(SyntaxError::Lexing)?;
+    let ast = Parser::new(tokens.iter().cloned())
+        .parse()
+        .map_err(SyntaxError::Parsing)?;
+    let mut formatter = Formatter {
+        ast: &ast,
+        tokens: &tokens,
+        next: 0,
+        hoisted: None,
+        parens: parens(&ast, &tokens),
+    };
+    // the tree is printed along with its tokens, in order, each one with the comments the
+    // lexer attached to it
+    let mut docs = formatter.expr(ast.root);
+    docs.extend(formatter.leading(true));
+    Ok(doc::print(&docs, options.width))
+}
+
+/// Counts the pairs of parentheses around each node of the source.
+///
+/// The tree does not keep parentheses, and the span of a node starts inside those around its
+/// first operand, so a pair belongs to the largest node starting right after the `(` and
+/// ending before the `)`. Those after a method name or a callee hold arguments, and those after
+/// `if`, `while` or `for` the header of a statement, and they are left out.
+fn parens(ast: &Ast, tokens: &[Token]) -> HashMap<NodeId, usize> {
+    let mut starts = NodeStarts(HashMap::new());
+    let Ok(()) = starts.visit_expr(ast, ast.root);
+    let mut callees = CalleeEnds(HashSet::new());
+    let Ok(()) = callees.visit_expr(ast, ast.root);
+    let mut parens = HashMap::new();
+    let mut open = Vec::new();
+    // indices of the `)` ending headers
+    let mut headers = HashSet::new();
+    for (i, tok) in tokens.iter().enumerate() {
+        match tok.typ {
+            TokenType::LEFTPAREN => {
+                let header = i > 0 && HEADER_KEYWORDS.contains(&tokens[i - 1].typ);
+                let call = header || opens_call(tokens, i, &callees.0, &headers);
+                open.push((header, (!call).then_some(i)));
+            }
+            TokenType::RIGHTPAREN => {
+                let (header, o) = open
+                    .pop()
+                    .expect("parentheses of a parsed source are balanced");
+                if header {
+                    headers.insert(i);
+                }
+                let Some(o) = o else {
+                    continue;
+                };
+                let first = tokens[o..]
+                    .iter()
+                    .find(|tok| tok.typ != TokenType::LEFTPAREN)
+                    .expect("parentheses of a parsed source are not empty");
+                let node = starts.0[&first.from_pos]
+                    .iter()
+                    .filter(|id| ast[**id].end <= tok.from_pos)
+                    .max_by_key(|id| (ast[**id].end, **id))
+                    .expect("parentheses of a parsed source hold an expression");
+                *parens.entry(*node).or_default() += 1;
+            }
+            _ => (),
+        }
+    }
+    parens
+}
+
+/// Whether the `(` at `i` holds arguments, following a method name or the callee of a call,
+/// which may itself be in parentheses as in `(f)(x)`, or parameters, following the name of a
+/// function or the `fun` of a lambda. The `)` of a header, at one of the indices in `headers`,
+/// ends a statement header rather than a callee.
+fn opens_call(
+    tokens: &[Token],
+    i: usize,
+    callees: &HashSet<Position>,
+    headers: &HashSet<usize>,
+) -> bool {
+    for (j, tok) in tokens[..i].iter().enumerate().rev() {
+        if [TokenType::IDENTIFIER, TokenType::FUN].contains(&tok.typ)
+            || callees.contains(&tok.to_pos)
+        {
+            return true;
+        }
+        if tok.typ != TokenType::RIGHTPAREN || headers.contains(&j) {
+            return false;
+        }
+    }
+    false
+}
+
+/// Keywords followed by a header in parentheses
+const HEADER_KEYWORDS: [TokenType; 3] = [TokenType::IF, TokenType::WHILE, TokenType::FOR];
+
+/// Positions where the callees of calls end
+struct CalleeEnds(HashSet<Position>);
+
+impl Visitor for CalleeEnds {
+    type Error = Infallible;
+
+    fn visit_call(&mut self, ast: &Ast, id: NodeId, call: &Call) -> Result<(), Infallible> {
+        self.0.insert(ast[call.callee].end);
+        walk_expr(self, ast, id)
+    }
+}
+
+/// Nodes by the position they start at
+struct NodeStarts(HashMap<Position, Vec<NodeId>>);
+
+impl Visitor for NodeStarts {
+    type Error = Infallible;
+
+    fn visit_expr(&mut self, ast: &Ast, id: NodeId) -> Result<(), Infallible> {
+        self.0.entry(ast[id].start).or_default().push(id);
+        walk_expr(self, ast, id)
+    }
+}
+
+struct Formatter<'a> {
+    ast: &'a Ast,
+    tokens: &'a [Token<'a>],
+    /// Index of the next token to print
+    next: usize,
+    /// Index of the token whose leading comments were already printed
+    hoisted: Option<usize>,
+    /// Number of pairs of parentheses around each node
+    parens: HashMap<NodeId, usize>,
+}
+
+impl<'a> Formatter<'a> {
+    /// Prints the comments before the next token, each on its own line unless it shares a line
+    /// with the token. With `dangling`, no code follows them before the end of their block.
+    fn leading(&self, dangling: bool) -> Vec<Doc> {
+        let trivia = &self.tokens[self.next].leading_trivia;
+        let mut docs = Vec::new();
+        for (i, comment) in trivia.iter().enumerate() {
+            if comment.kind == TriviaKind::Whitespace {
+                continue;
+            }
+            let comment_text = comment.text.trim_end();
+            let breaks = match trivia.get(i + 1) {
+                Some(next) if next.kind == TriviaKind::Whitespace => {
+                    next.text.matches('\n').count()
+                }
+                _ => 0,
+            };
+            if comment.text.starts_with("//") || breaks > 0 {
+                docs.push(Doc::FreshLine);
+                docs.push(text(comment_text));
+                if !dangling {
+                    docs.push(Doc::HardLine);
+                }
+                // a blank line after the comment is kept
+                if breaks > 1 {
+                    docs.push(Doc::HardLine);
+                }
+            } else if dangling {
+                docs.push(text(format!(" {}", comment_text)));
+            } else {
+                docs.push(text(format!("{} ", comment_text)));
+            }
+        }
+        docs
+    }
+
+    /// Prints the next token as `doc`, followed by the comments after it on its line
+    fn token_as(&mut self, doc: Doc) -> Vec<Doc> {
+        let tokens = self.tokens;
+        let tok = &tokens[self.next];
+        self.next += 1;
+        let mut docs = vec![doc];
+        let trivia = &tok.trailing_trivia;
+        for (i, comment) in trivia.iter().enumerate() {
+            if comment.kind == TriviaKind::Whitespace {
+                continue;
+            }
+            let comment_text = format!(" {}", comment.text.trim_end());
+            // trailing trivia ends with the line break, if there is one
+            let ends_line = comment.text.starts_with("//")
+                || trivia[i + 1..]
+                    .iter()
+                    .any(|next| next.kind == TriviaKind::Whitespace && next.text.contains('\n'))
+                || tokens[self.next].typ == TokenType::EOF;
+            if ends_line {
+                docs.push(Doc::LineSuffix(comment_text));
+            } else {
+                docs.push(text(comment_text));
+            }
+        }
+        docs
+    }
+
+    /// Prints the comments before the next token ahead of the groups it starts, which they
+    /// would otherwise break.
+    fn hoist_leading(&mut self) -> Vec<Doc> {
+        if self.hoisted == Some(self.next) {
+            return Vec::new();
+        }
+        self.hoisted = Some(self.next);
+        self.leading(false)
+    }
+
+    /// Prints the next token with its comments
+    fn token(&mut self) -> Vec<Doc> {
+        let mut docs = self.hoist_leading();
+        let lexeme = self.tokens[self.next].lexeme.to_string();
+        docs.extend(self.token_as(text(lexeme)));
+        docs
+    }
+
+    /// Prints a node with the parentheses around it
+    fn expr(&mut self, id: NodeId) -> Vec<Doc> {
+        let parens = self.parens.get(&id).copied().unwrap_or(0);
+        let mut docs = self.hoist_leading();
+        docs.extend(self.parenthesized(id, parens));
+        docs
+    }
+
+    fn parenthesized(&mut self, id: NodeId, parens: usize) -> Vec<Doc> {
+        if parens == 0 {
+            return self.bare(id);
+        }
+        let mut docs = self.token();
+        let mut inner = vec![Doc::SoftLine];
+        inner.extend(self.parenthesized(id, parens - 1));
+        inner.extend(self.leading(true));
+        docs.extend([Doc::Indent(inner), Doc::SoftLine]);
+        docs.extend(self.token_as(text(")")));
+        vec![Doc::Group(docs)]
+    }
+
+    /// Prints a node without the parentheses around it
+    fn bare(&mut self, id: NodeId) -> Vec<Doc> {
+        let ast = self.ast;
+        match &ast[id].expr {
+            // negative patterns are made of two tokens
+            Expr::Num(_)
+            | Expr::Float(_)
+            | Expr::Str(_)
+            | Expr::T
+            | Expr::F
+            | Expr::Nil
+            | Expr::Variable(_) => {
+                let mut docs = Vec::new();
+                while self.tokens[self.next].from_pos < ast[id].end {
+                    docs.extend(self.token());
+                }
+                docs
+            }
+            Expr::Unary { e1, .. } => {
+                let mut docs = self.token();
+                let operand = self.expr(*e1);
+                // `- -x` must not be glued into `--x`
+                if first_text(&docs) == Some("-")
+                    && first_text(&operand).is_some_and(|s| s.starts_with('-'))
+                {
+                    docs.push(text(" "));
+                }
+                docs.extend(operand);
+                docs
+            }
+            Expr::Binary { .. } => self.binary(id),
+            Expr::Conditional { cond, e1, e2 } => {
+                let mut docs = self.expr(*cond);
+                let mut branches = vec![Doc::Line];
+                branches.extend(self.token());
+                branches.push(text(" "));
+                branches.extend(self.expr(*e1));
+                branches.push(Doc::Line);
+                branches.extend(self.token());
+                branches.push(text(" "));
+                branches.extend(self.expr(*e2));
+                docs.push(Doc::Indent(branches));
+                vec![Doc::Group(docs)]
+            }
+            Expr::Comma { .. } => {
+                let mut operands = Vec::new();
+                let mut left = id;
+                while let Expr::Comma { e1, e2 } = ast[left].expr {
+                    if left != id && self.parens.contains_key(&left) {
+                        break;
+                    }
+                    operands.push(e2);
+                    left = e1;
+                }
+                operands.push(left);
+                operands.reverse();
+                let mut docs = self.expr(operands[0]);
+                for operand in &operands[1..] {
+                    docs.extend(self.token());
+                    docs.push(Doc::Line);
+                    docs.extend(self.expr(*operand));
+                }
+                vec![Doc::Group(docs)]
+            }
+            Expr::Match { subject, arms } => self.match_expr(*subject, arms),
+            Expr::List(elements) => self.delimited(elements.len(), |f, i| f.expr(elements[i])),
+            Expr::Map(entries) => self.delimited(entries.len(), |f, i| {
+                let (key, value) = entries[i];
+                let mut docs = f.expr(key);
+                docs.extend(f.token());
+                docs.push(text(" "));
+                docs.extend(f.expr(value));
+                docs
+            }),
+            Expr::Index { e1, index } => {
+                let mut docs = self.expr(*e1);
+                docs.extend(self.bracketed(|f| f.expr(*index)));
+                docs
+            }
+            Expr::Slice { e1, from, to } => {
+                let mut docs = self.expr(*e1);
+                docs.extend(self.bracketed(|f| {
+                    let mut docs = from.map_or(Vec::new(), |from| f.expr(from));
+                    docs.extend(f.token());
+                    docs.extend(to.map_or(Vec::new(), |to| f.expr(to)));
+                    docs
+                }));
+                docs
+            }
+            Expr::MethodCall(call) => {
+                let mut docs = self.expr(call.receiver);
+                docs.extend(self.token());
+                docs.extend(self.token());
+                docs.extend(self.arguments(&call.args));
+                docs
+            }
+            Expr::Call(call) => {
+                let mut docs = self.expr(call.callee);
+                docs.extend(self.arguments(&call.args));
+                docs
+            }
+            Expr::Assign { target, op, value } => match (op, value) {
+                (AssignOp::PreStep(_), _) => {
+                    let mut docs = self.token();
+                    docs.extend(self.expr(*target));
+                    docs
+                }
+                (AssignOp::PostStep(_), _) => {
+                    let mut docs = self.expr(*target);
+                    docs.extend(self.token());
+                    docs
+                }
+                (_, Some(value)) => {
+                    // broken after the operator, so that the target stays on its line
+                    let mut docs = self.expr(*target);
+                    docs.push(text(" "));
+                    docs.extend(self.token());
+                    docs.extend(self.assigned(*value));
+                    vec![Doc::Group(docs)]
+                }
+                (_, None) => unreachable!("only steps have no value"),
+            },
+            Expr::Function(function) => self.function(function),
+            Expr::Program { statements, value } => {
+                let mut docs = self.statements(statements);
+                if let Some(value) = value {
+                    docs.extend(self.statement_break());
+                    docs.extend(self.expr(*value));
+                }
+                docs
+            }
+            // a `for` loop with an initializer is a block holding it and a `while` loop
+            Expr::Block(statements) if self.tokens[self.next].typ == TokenType::FOR => {
+                self.for_loop(Some(statements[0]), statements[1])
+            }
+            Expr::Block(statements) => self.block(statements),
+            Expr::Var(var) => self.var(var),
+            Expr::Return(value) => {
+                let mut docs = self.token();
+                if let Some(value) = value {
+                    docs.push(text(" "));
+                    docs.extend(self.expr(*value));
+                }
+                docs.extend(self.token());
+                docs
+            }
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let mut docs = self.token();
+                docs.push(text(" "));
+                docs.extend(self.header(|f| f.expr(*cond)));
+                let braced = self.is_braced_block(*then);
+                docs.extend(self.branch(*then));
+                if let Some(otherwise) = otherwise {
+                    // `else` follows the `}` of a block, and goes on its own line otherwise
+                    match braced {
+                        true => docs.push(text(" ")),
+                        false => docs.push(Doc::HardLine),
+                    }
+                    docs.extend(self.token());
+                    // `else if` chains stay flat
+                    match self.tokens[self.next].typ {
+                        TokenType::IF => {
+                            docs.push(text(" "));
+                            docs.extend(self.expr(*otherwise));
+                        }
+                        _ => docs.extend(self.branch(*otherwise)),
+                    }
+                }
+                docs
+            }
+            Expr::While { .. } if self.tokens[self.next].typ == TokenType::FOR => {
+                self.for_loop(None, id)
+            }
+            Expr::While { cond, body, .. } => {
+                let mut docs = self.token();
+                docs.push(text(" "));
+                docs.extend(self.header(|f| f.expr(*cond)));
+                docs.extend(self.branch(*body));
+                docs
+            }
+            Expr::ForIn(for_in) => {
+                let mut docs = self.token();
+                docs.push(text(" "));
+                docs.extend(self.header(|f| {
+                    // `var`, the name and `in`
+                    let mut docs = f.token();
+                    for _ in 0..2 {
+                        docs.push(text(" "));
+                        docs.extend(f.token());
+                    }
+                    docs.push(text(" "));
+                    docs.extend(f.expr(for_in.iterable));
+                    docs
+                }));
+                docs.extend(self.branch(for_in.body));
+                docs
+            }
+            Expr::Break | Expr::Continue => {
+                let mut docs = self.token();
+                docs.extend(self.token());
+                docs
+            }
+        }
+    }
+
+    /// Prints statements one per line
+    fn statements(&mut self, statements: &[NodeId]) -> Vec<Doc> {
+        let mut docs = Vec::new();
+        for (i, statement) in statements.iter().enumerate() {
+            if i > 0 {
+                docs.extend(self.statement_break());
+            }
+            docs.extend(self.statement(*statement));
+        }
+        docs
+    }
+
+    /// Prints a statement, followed by its `;` if it is an expression
+    fn statement(&mut self, id: NodeId) -> Vec<Doc> {
+        let mut docs = self.expr(id);
+        if self.tokens[self.next].typ == TokenType::SEMICOLON {
+            docs.extend(self.token());
+        }
+        docs
+    }
+
+    /// Prints the statement of a branch or the body of a loop: a block after a space, and
+    /// anything else on the same line if it fits, and otherwise on the next line
+    fn branch(&mut self, id: NodeId) -> Vec<Doc> {
+        if self.is_braced_block(id) {
+            let mut docs = vec![text(" ")];
+            docs.extend(self.expr(id));
+            return docs;
+        }
+        let mut inner = vec![Doc::Line];
+        inner.extend(self.statement(id));
+        vec![Doc::Group(vec![Doc::Indent(inner)])]
+    }
+
+    /// Whether the statement, which is printed next, is a block written with braces rather
+    /// than a `for` loop
+    fn is_braced_block(&self, id: NodeId) -> bool {
+        matches!(self.ast[id].expr, Expr::Block(_))
+            && self.tokens[self.next].typ == TokenType::LEFTBRACE
+    }
+
+    /// Prints what `inner` prints between the next token and its `)`, for the header of an
+    /// `if` or a loop
+    fn header(&mut self, inner: impl FnOnce(&mut Self) -> Vec<Doc>) -> Vec<Doc> {
+        let mut docs = self.token();
+        docs.extend(inner(self));
+        docs.extend(self.token_as(text(")")));
+        docs
+    }
+
+    /// Prints a `for` loop from the `while` loop it became and its initializer. A missing
+    /// condition has no tokens, the parser making it up.
+    fn for_loop(&mut self, init: Option<NodeId>, looping: NodeId) -> Vec<Doc> {
+        let Expr::While { cond, body, step } = self.ast[looping].expr else {
+            unreachable!("for_loop() is called on loops")
+        };
+        let mut docs = self.token();
+        docs.push(text(" "));
+        docs.extend(self.header(|f| {
+            let mut docs = match init {
+                Some(init) => f.statement(init),
+                None => f.token(),
+            };
+            if f.tokens[f.next].typ != TokenType::SEMICOLON {
+                docs.push(text(" "));
+                docs.extend(f.expr(cond));
+            }
+            docs.extend(f.token());
+            if let Some(step) = step {
+                docs.push(text(" "));
+                docs.extend(f.expr(step));
+            }
+            docs
+        }));
+        docs.extend(self.branch(body));
+        docs
+    }
+
+    /// Goes to the line of the next statement, leaving a blank line before it if the source has
+    /// one
+    fn statement_break(&self) -> Vec<Doc> {
+        // the line break ending the previous statement is part of its trailing trivia
+        let blank = self.tokens[self.next]
+            .leading_trivia
+            .first()
+            .is_some_and(|trivia| {
+                trivia.kind == TriviaKind::Whitespace && trivia.text.contains('\n')
+            });
+        match blank {
+            true => vec![Doc::HardLine, Doc::HardLine],
+            false => vec![Doc::HardLine],
+        }
+    }
+
+    /// Prints a block with its statements indented on their own lines, even when it is empty
+    /// but holds comments
+    fn block(&mut self, statements: &[NodeId]) -> Vec<Doc> {
+        let mut docs = self.token();
+        let mut inner = Vec::new();
+        if !statements.is_empty() {
+            inner.push(Doc::HardLine);
+            inner.extend(self.statements(statements));
+        }
+        inner.extend(self.leading(true));
+        if !inner.is_empty() {
+            docs.extend([Doc::Indent(inner), Doc::HardLine]);
+        }
+        docs.extend(self.token_as(text("}")));
+        docs
+    }
+
+    /// Prints a function declaration or a lambda, with its parameters like the arguments of a
+    /// call
+    fn function(&mut self, function: &Function) -> Vec<Doc> {
+        let mut docs = self.token();
+        docs.push(text(" "));
+        if function.name.is_some() {
+            docs.extend(self.token());
+        }
+        docs.extend(self.delimited(function.params.len(), |f, _| f.token()));
+        docs.push(text(" "));
+        docs.extend(self.expr(function.body));
+        docs
+    }
+
+    /// Prints a variable declaration, broken after its `=` like an assignment
+    fn var(&mut self, var: &Var) -> Vec<Doc> {
+        let mut docs = self.token();
+        docs.push(text(" "));
+        docs.extend(self.token());
+        if let Some(init) = var.init {
+            docs.push(text(" "));
+            docs.extend(self.token());
+            docs.extend(self.assigned(init));
+        }
+        docs.extend(self.token());
+        vec![Doc::Group(docs)]
+    }
+
+    /// Prints the value after an `=`, on the next line if it does not fit, except for a
+    /// function, which stays after the `=` and breaks its body instead
+    fn assigned(&mut self, value: NodeId) -> Vec<Doc> {
+        if let Expr::Function(_) = self.ast[value].expr {
+            let mut docs = vec![text(" ")];
+            docs.extend(self.expr(value));
+            return docs;
+        }
+        let mut rest = vec![Doc::Line];
+        rest.extend(self.expr(value));
+        vec![Doc::Indent(rest)]
+    }
+
+    /// Prints the arguments of a call. A lambda passed alone stays on the line of the call,
+    /// breaking its body rather than the arguments, and loses its trailing comma.
+    fn arguments(&mut self, args: &[NodeId]) -> Vec<Doc> {
+        match *args {
+            [arg]
+                if matches!(self.ast[arg].expr, Expr::Function(_))
+                    && !self.parens.contains_key(&arg) =>
+            {
+                let mut docs = self.token();
+                docs.extend(self.expr(arg));
+                if self.tokens[self.next].typ == TokenType::COMMA {
+                    docs.extend(self.token_as(text("")));
+                }
+                docs.extend(self.leading(true));
+                docs.extend(self.token_as(text(")")));
+                docs
+            }
+            _ => self.delimited(args.len(), |f, i| f.expr(args[i])),
+        }
+    }
+
+    /// Prints what `inner` prints between the next token and its closing bracket
+    fn bracketed(&mut self, inner: impl FnOnce(&mut Self) -> Vec<Doc>) -> Vec<Doc> {
+        let mut docs = self.token();
+        let mut inside = vec![Doc::SoftLine];
+        inside.extend(inner(self));
+        inside.extend(self.leading(true));
+        docs.extend([Doc::Indent(inside), Doc::SoftLine]);
+        docs.extend(self.token_as(text("]")));
+        vec![Doc::Group(docs)]
+    }
+
+    /// Prints the `count` items of a list, a map or the arguments of a call, along with the
+    /// delimiters around them, on a single line if they fit and otherwise one per line, each
+    /// followed by a comma.
+    fn delimited(
+        &mut self,
+        count: usize,
+        mut item: impl FnMut(&mut Self, usize) -> Vec<Doc>,
+    ) -> Vec<Doc> {
+        let mut docs = self.token();
+        let mut inner = Vec::new();
+        for i in 0..count {
+            inner.push(if i == 0 { Doc::SoftLine } else { Doc::Line });
+            inner.extend(item(self, i));
+            let last = i + 1 == count;
+            if !last {
+                inner.extend(self.token());
+            } else if self.tokens[self.next].typ == TokenType::COMMA {
+                inner.extend(self.leading(false));
+                inner.extend(self.token_as(Doc::IfBreak(",")));
+            } else {
+                inner.push(Doc::IfBreak(","));
+            }
+        }
+        inner.extend(self.leading(true));
+        if !inner.is_empty() {
+            docs.extend([Doc::Indent(inner), Doc::SoftLine]);
+        }
+        let close = self.tokens[self.next].lexeme.to_string();
+        docs.extend(self.token_as(text(close)));
+        vec![Doc::Group(docs)]
+    }
+
+    /// Prints a chain of binary operators of the same precedence, such as `a + b - c`, breaking
+    /// it before the operators when it does not fit.
+    fn binary(&mut self, id: NodeId) -> Vec<Doc> {
+        let Expr::Binary { op, .. } = self.ast[id].expr else {
+            unreachable!("binary() is called on binary nodes")
+        };
+        let Operator { bp, assoc, .. } = *operators::binary(op);
+        let mut operands = Vec::new();
+        let mut node = id;
+        // the chain goes down the left operands, or the right ones for right associativity
+        while let Expr::Binary { e1, op, e2 } = self.ast[node].expr {
+            if (node != id && self.parens.contains_key(&node)) || operators::binary(op).bp != bp {
+                break;
+            }
+            let (next, other) = match assoc {
+                Assoc::Left => (e1, e2),
+                Assoc::Right => (e2, e1),
+            };
+            operands.push(other);
+            node = next;
+        }
+        operands.push(node);
+        if assoc == Assoc::Left {
+            operands.reverse();
+        }
+
+        // ranges are written without spaces, like `0..10`
+        let glued = matches!(op, BinaryOp::Range | BinaryOp::RangeInclusive);
+        let mut docs = self.expr(operands[0]);
+        let mut rest = Vec::new();
+        for operand in &operands[1..] {
+            rest.push(if glued { Doc::SoftLine } else { Doc::Line });
+            rest.extend(self.token());
+            if !glued {
+                rest.push(text(" "));
+            }
+            rest.extend(self.expr(*operand));
+        }
+        docs.push(Doc::Indent(rest));
+        vec![Doc::Group(docs)]
+    }
+
+    /// Prints a match expression, with its arms on a single line if they fit, and otherwise
+    /// one per line, each followed by a comma.
+    fn match_expr(&mut self, subject: NodeId, arms: &[MatchArm]) -> Vec<Doc> {
+        let mut docs = self.token();
+        docs.push(text(" "));
+        docs.extend(self.expr(subject));
+        docs.push(text(" "));
+        docs.extend(self.token());
+        let mut inner = Vec::new();
+        for (i, arm) in arms.iter().enumerate() {
+            inner.push(Doc::Line);
+            inner.extend(self.hoist_leading());
+            inner.push(Doc::Group(self.arm(arm)));
+            let last = i + 1 == arms.len();
+            if !last {
+                inner.extend(self.token());
+            } else if self.tokens[self.next].typ == TokenType::COMMA {
+                inner.extend(self.leading(false));
+                inner.extend(self.token_as(Doc::IfBreak(",")));
+            } else {
+                inner.push(Doc::IfBreak(","));
+            }
+        }
+        inner.extend(self.leading(true));
+        if !inner.is_empty() {
+            docs.extend([Doc::Indent(inner), Doc::Line]);
+        }
+        docs.extend(self.token_as(text("}")));
+        vec![Doc::Group(docs)]
+    }
+
+    fn arm(&mut self, arm: &MatchArm) -> Vec<Doc> {
+        let mut docs = Vec::new();
+        for (i, pattern) in arm.patterns.iter().enumerate() {
+            if i > 0 {
+                docs.push(text(" "));
+                docs.extend(self.token());
+                docs.push(text(" "));
+            }
+            match pattern {
+                Pattern::Wildcard => docs.extend(self.token()),
+                Pattern::Literal(literal) => docs.extend(self.expr(*literal)),
+                Pattern::Is(class) => {
+                    docs.extend(self.token());
+                    docs.push(text(" "));
+                    docs.extend(self.expr(*class));
+                }
+            }
+        }
+        if let Some(guard) = arm.guard {
+            docs.push(text(" "));
+            docs.extend(self.token());
+            docs.push(text(" "));
+            docs.extend(self.expr(guard));
+        }
+        docs.push(text(" "));
+        docs.extend(self.token());
+        docs.push(text(" "));
+        docs.extend(self.expr(arm.body));
+        docs
+    }
+}
+
+/// The first text printed by the documents
+fn first_text(docs: &[Doc]) -> Option<&str> {
+    docs.iter().find_map(|doc| match doc {
+        Doc::Text(s) => Some(s.as_str()),
+        Doc::Indent(docs) | Doc::Group(docs) => first_text(docs),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{parser::parse, printer};
+
+    /// Tree of the source as an s-expression
+    fn sexpr(src: &str) -> String {
+        printer::sexpr(&parse(src).expect(src))
+    }
+
+    /// Text of the comments of the source, in order
+    fn comments(src: &str) -> Vec<String> {
+        let tokens = Lexer::new(src)
+            .scan_lossless()
+            .unwrap_or_else(|errors| panic!("{:?} should lex, got {:?}", src, errors));
+        tokens
+            .iter()
+            .flat_map(|tok| tok.leading_trivia.iter().chain(&tok.trailing_trivia))
+            .filter(|trivia| trivia.kind != TriviaKind::Whitespace)
+            .map(|trivia| trivia.text.trim_end().to_string())
+            .collect()
+    }
+
+    fn format_at(src: &str, width: usize) -> String {
+        format(src, &FormatOptions { width })
+            .unwrap_or_else(|_| panic!("{:?} should format at width {}", src, width))
+    }
+
+    const FIXTURES: &[&str] = &[
+        "1+2*3",
+        "aaaa ? bbbb + cccc * dddd : eeee ? ffff : gggg, hhhh - iiii",
+        "[1, 2, 3, [4, 5], {\"key\": [6, 7], 8: nil,}][0][1:]",
+        "f(1, 2)(3).push(len(\"abc\"), -0x1f, 1_000, 2.5e3) // trailing\n",
+        "match x { 1 | -2 if y => \"one\", 2.5 => [x][0] += 1, _ => nil }",
+        "/// doc\n(1 + /* inline */ 2) ** (3 ~/ 4) // end\n",
+        "1..10, 0..=n, [5][0]++, --[5][0], [1][0] = [2][0] = 3",
+        "(\n\n1, // one\n\n2 /* two */\n)",
+        "((((1 + 2)))) * -(-(3)), !(true == false) != ~7 << 2",
+        "({\n  // before\n  \"a\": 1, // after\n})",
+        // a group measured as flat although a following group breaks, from the lists change
+        "[[1]][0](\"s\",/* */[(0x1f?f:1,// c3\n\"\")]?((/**/7)):(f))()",
+        // a space kept in front of text moved to a new line by an end-of-line comment
+        "({//\n/**/}),1",
+        // multi-line block comments taken for the end of a line, from the natives change
+        "1 ? 2 : [/**//*\n*/]",
+        "x ? h(/**//*\n*/) : 3",
+        "fun f(a, b,) { var c = a; return c + b; }\n\n// then\nvar g = fun () {}; f(g(), {})",
+        "{ var x; { } // empty\n x = fun (n) { return; }; } ({1: 2})[1]; return_value",
+        "for (var i = 0; i < 10; i++) { if (i == 2) continue; else if (i > 5) break; }",
+        "f(fun (a) { return a; }, ); g.h(fun () {} /* c */)(fun () { return; }(1), 2);",
+        "while ((a)) /* c */ (b)++; for (;;) if (x) y = 1; else { y = 2; } for (x = 0; ; ) x;",
+        "for(var  x in /* c */[1,2]){f(x);}for (var c in\"ab\") for (var i in 0..=c.len()) break;",
+    ];
+
+    #[test]
+    fn formatting_is_idempotent_and_keeps_the_tree() {
+        for src in FIXTURES {
+            for width in [10, 20, 40, 80] {
+                let formatted = format_at(src, width);
+                assert_eq!(
+                    format_at(&formatted, width),
+                    formatted,
+                    "formatting {:?} twice at width {}",
+                    src,
+                    width
+                );
+                assert_eq!(
+                    sexpr(&formatted),
+                    sexpr(src),
+                    "tree of {:?} formatted at width {}",
+                    src,
+                    width
+                );
+                assert_eq!(
+                    comments(&formatted),
+                    comments(src),
+                    "comments of {:?} formatted at width {}",
+                    src,
+                    width
+                );
+            }
+        }
+    }
+}
@@ -1,120 +1,1595 @@
-use crate::{error::LoxError, parsing::expr::*};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    error::LoxError,
+    interpreting::{iterate::LoxIter, map::LoxMap, natives},
+    parsing::{
+        expr::{self, *},
+        visit::Visitor,
+    },
+    position::Position,
+};
 use LoxValue::*;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Clone)]
 pub enum LoxValue {
     Int(i64),
+    Float(f64),
     Bool(bool),
     Strng(String),
     Nil,
+    /// Shared between its copies, so that changes through one show through the others
+    List(Rc<RefCell<Vec<LoxValue>>>),
+    /// Shared like lists
+    Map(Rc<RefCell<LoxMap>>),
+    /// Integers from the start up to the end, which is left out unless the range is inclusive
+    Range {
+        start: i64,
+        end: i64,
+        inclusive: bool,
+    },
+    NativeFunction(Rc<NativeFunction>),
+    Function(Rc<LoxFunction>),
 }
 
-pub fn interpret(pexpr: &PositionedExpr) -> Result<LoxValue, LoxError> {
-    match &pexpr.expr {
-        Expr::Nil => Ok(Nil),
-        Expr::Num(x) => Ok(Int(*x)),
-        Expr::Str(s) => Ok(Strng(s.to_string())),
-        Expr::T => Ok(Bool(true)),
-        Expr::F => Ok(Bool(false)),
-        Expr::Unary { op, e1 } => match (op, interpret(e1.as_ref())) {
-            (_, Err(err)) => Err(err),
-            (UnaryOp::UnaMinus, Ok(Int(x))) => Ok(Int(-x)),
-            (UnaryOp::UnaMinus, Ok(Bool(_))) => Err(LoxError {
-                msg: "opposite of bool is undefined".to_string(),
-            }),
-            (UnaryOp::UnaMinus, Ok(Strng(_))) => Err(LoxError {
-                msg: "opposite of string is undefined".to_string(),
-            }),
-            (UnaryOp::UnaMinus, Ok(Nil)) => Err(LoxError {
-                msg: "opposite of nil is undefined".to_string(),
-            }),
-            (UnaryOp::Not, Ok(Int(_))) => Err(LoxError {
-                msg: "negation of int is undefined".to_string(),
-            }),
-            (UnaryOp::Not, Ok(Bool(b))) => Ok(Bool(!b)),
-            (UnaryOp::Not, Ok(Strng(_))) => Err(LoxError {
-                msg: "negation of string is undefined".to_string(),
-            }),
-            (UnaryOp::Not, Ok(Nil)) => Err(LoxError {
-                msg: "negation of nil is undefined".to_string(),
-            }),
-        },
+/// Function written in Rust, which Lox code calls like any other
+pub struct NativeFunction {
+    pub name: Box<str>,
+    pub arity: Arity,
+    pub fun: Box<NativeFn>,
+}
 
-        Expr::Binary { e1, op, e2 } => match (interpret(e1.as_ref()), op, interpret(e2.as_ref())) {
-            (Err(e1), _, _) => Err(e1),
-            (_, _, Err(e2)) => Err(e2),
-            (Ok(i1), BinaryOp::Equalequal, Ok(i2)) => Ok(Bool(i1 == i2)),
-            (Ok(i1), BinaryOp::NotEqual, Ok(i2)) => Ok(Bool(i1 != i2)),
-            (Ok(i1), BinaryOp::Le, Ok(i2)) => {
-                if let (Int(n1), Int(n2)) = (i1, i2) {
-                    Ok(Bool(n1 < n2))
-                } else {
-                    Err(LoxError {
-                        msg: "Comparison should be between numbers only".to_string(),
-                    })
-                }
+/// Body of a native function, given the arguments of a call
+pub type NativeFn = dyn Fn(&[LoxValue]) -> Result<LoxValue, LoxError>;
+
+/// Number of arguments a function takes, checked before it is called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    /// Any number of arguments from the given one, like `max(a, b, ...)`
+    AtLeast(usize),
+}
+
+impl NativeFunction {
+    pub fn call(&self, args: &[LoxValue]) -> Result<LoxValue, LoxError> {
+        let accepted = match self.arity {
+            Arity::Exact(arity) => args.len() == arity,
+            Arity::AtLeast(arity) => args.len() >= arity,
+        };
+        if !accepted {
+            let expected = match self.arity {
+                Arity::Exact(arity) => arity.to_string(),
+                Arity::AtLeast(arity) => format!("at least {}", arity),
+            };
+            return Err(LoxError::new(format!(
+                "Function {} takes {} argument(s) but was given {}",
+                self.name,
+                expected,
+                args.len()
+            )));
+        }
+        (self.fun)(args)
+    }
+}
+
+/// Functions are only equal to themselves.
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &NativeFunction) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Function written in Lox, which keeps the tree it was declared in and the scope it was
+/// declared in, whose variables it can go on using after the scope ends
+pub struct LoxFunction {
+    ast: Rc<Ast>,
+    /// Its `Expr::Function` node
+    id: NodeId,
+    closure: Option<Rc<Scope>>,
+}
+
+impl LoxFunction {
+    fn declaration(&self) -> &expr::Function {
+        match &self.ast[self.id].expr {
+            Expr::Function(function) => function,
+            _ => unreachable!("functions are made from their declaration"),
+        }
+    }
+
+    /// Where the function is declared, which tells lambdas apart
+    pub fn position(&self) -> Position {
+        self.ast[self.id].start
+    }
+}
+
+/// Functions are only equal to themselves, each evaluation of a declaration making a new one.
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &LoxFunction) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Named functions are shown by their name, lambdas by where they are declared.
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.declaration().name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<fn at {}>", self.position().pretty_print()),
+        }
+    }
+}
+
+/// Variables declared in a block or for a call, nested in the scope around them
+struct Scope {
+    vars: RefCell<HashMap<Box<str>, LoxValue>>,
+    enclosing: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    fn new(enclosing: Option<Rc<Scope>>) -> Rc<Scope> {
+        Rc::new(Scope {
+            vars: RefCell::new(HashMap::new()),
+            enclosing,
+        })
+    }
+}
+
+impl LoxValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Int(_) => "int",
+            Float(_) => "float",
+            Bool(_) => "bool",
+            Strng(_) => "string",
+            Nil => "nil",
+            List(_) => "list",
+            Map(_) => "map",
+            Range { .. } => "range",
+            NativeFunction(_) | Function(_) => "function",
+        }
+    }
+}
+
+/// Lists and maps are printed with their elements, leaving out the cell they live in.
+impl fmt::Debug for LoxValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Int(x) => f.debug_tuple("Int").field(x).finish(),
+            Float(x) => f.debug_tuple("Float").field(x).finish(),
+            Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            Strng(s) => f.debug_tuple("Strng").field(s).finish(),
+            Nil => write!(f, "Nil"),
+            List(list) => f.debug_tuple("List").field(&list.borrow()).finish(),
+            Map(map) => f.debug_tuple("Map").field(&map.borrow()).finish(),
+            Range {
+                start,
+                end,
+                inclusive: false,
+            } => f.debug_tuple("Range").field(&(start..end)).finish(),
+            Range {
+                start,
+                end,
+                inclusive: true,
+            } => f.debug_tuple("Range").field(&(start..=end)).finish(),
+            NativeFunction(fun) => write!(f, "NativeFunction({})", fun.name),
+            Function(fun) => write!(f, "Function({})", fun),
+        }
+    }
+}
+
+/// Values as Lox code shows them, with strings inside lists and maps in quotes.
+impl fmt::Display for LoxValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nested = |value: &LoxValue| match value {
+            Strng(s) => format!("{:?}", s),
+            _ => value.to_string(),
+        };
+        match self {
+            Int(x) => write!(f, "{}", x),
+            Float(x) => write!(f, "{:?}", x),
+            Bool(b) => write!(f, "{}", b),
+            Strng(s) => write!(f, "{}", s),
+            Nil => write!(f, "nil"),
+            List(list) => {
+                let elements: Vec<String> = list.borrow().iter().map(nested).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Map(map) => {
+                let entries: Vec<String> = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", nested(key), nested(value)))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
             }
-            (Ok(i1), BinaryOp::Leq, Ok(i2)) => {
-                if let (Int(n1), Int(n2)) = (i1, i2) {
-                    Ok(Bool(n1 <= n2))
-                } else {
-                    Err(LoxError {
-                        msg: "Comparison should be between numbers only".to_string(),
-                    })
+            Range {
+                start,
+                end,
+                inclusive,
+            } => write!(f, "{}..{}{}", start, if *inclusive { "=" } else { "" }, end),
+            NativeFunction(fun) => write!(f, "<native fn {}>", fun.name),
+            Function(fun) => write!(f, "{}", fun),
+        }
+    }
+}
+
+/// Runs a program and gives its value, with the core library defined.
+pub fn interpret(ast: impl Into<Rc<Ast>>) -> Result<LoxValue, LoxError> {
+    Interpreter::new().interpret(ast)
+}
+
+/// Calls of Lox functions that can be nested, so that endless recursion fails rather than
+/// overflowing the stack. Each call takes up to about 10 KiB of stack in debug builds, so the
+/// interpreter needs a thread with a stack of 16 MiB to reach the limit.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+/// Why the evaluation of a node stopped before its end: an error, or a statement jumping out of
+/// the function or the loop around it
+#[derive(Debug)]
+pub enum Unwind {
+    Error(LoxError),
+    Return(LoxValue),
+    Break,
+    Continue,
+}
+
+impl From<LoxError> for Unwind {
+    fn from(err: LoxError) -> Unwind {
+        Unwind::Error(err)
+    }
+}
+
+/// Tree-walking evaluator. Visiting an expression leaves its value in `value`.
+pub struct Interpreter {
+    value: LoxValue,
+    /// Values that names refer to, such as the native functions, and variables declared by
+    /// the statements of the program
+    globals: HashMap<Box<str>, LoxValue>,
+    /// Innermost scope, or None outside of blocks and functions
+    scope: Option<Rc<Scope>>,
+    /// Tree being evaluated, which functions declared in it keep
+    ast: Rc<Ast>,
+    /// Number of Lox functions being called
+    depth: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with the core library defined, its random numbers seeded from the clock
+    pub fn new() -> Interpreter {
+        Interpreter::with_seed(natives::clock_seed())
+    }
+
+    /// An interpreter with the core library defined, whose random numbers follow from `seed`
+    pub fn with_seed(seed: u64) -> Interpreter {
+        let mut interpreter = Interpreter {
+            value: Nil,
+            globals: HashMap::new(),
+            scope: None,
+            ast: Rc::default(),
+            depth: 0,
+        };
+        natives::register_core(&mut interpreter, seed);
+        interpreter
+    }
+
+    /// Defines a global, replacing any previous value of the name
+    pub fn define(&mut self, name: &str, value: LoxValue) {
+        self.globals.insert(name.into(), value);
+    }
+
+    /// Defines a global native function, which Lox code can then call by its name
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(&[LoxValue]) -> Result<LoxValue, LoxError> + 'static,
+    ) {
+        let fun = NativeFunction {
+            name: name.into(),
+            arity,
+            fun: Box::new(fun),
+        };
+        self.define(name, NativeFunction(Rc::new(fun)));
+    }
+
+    /// Runs a program and gives its value. Variables and functions it declares outside of
+    /// blocks stay defined for the programs run next.
+    pub fn interpret(&mut self, ast: impl Into<Rc<Ast>>) -> Result<LoxValue, LoxError> {
+        let ast = ast.into();
+        let enclosing = std::mem::replace(&mut self.ast, ast.clone());
+        let value = self.eval(&ast, ast.root);
+        self.ast = enclosing;
+        match value {
+            Ok(value) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err),
+            // the parser rejects these, but trees can also be deserialized
+            Err(Unwind::Return(_)) => Err(LoxError::new("Cannot return from outside a function")),
+            Err(Unwind::Break | Unwind::Continue) => Err(outside_loop()),
+        }
+    }
+
+    /// Visits an expression and takes its value
+    fn eval(&mut self, ast: &Ast, id: NodeId) -> Result<LoxValue, Unwind> {
+        self.visit_expr(ast, id)?;
+        Ok(std::mem::replace(&mut self.value, Nil))
+    }
+
+    /// Runs statements in a new scope nested in the current one, which starts with `vars`
+    fn execute_in_scope(
+        &mut self,
+        ast: &Ast,
+        statements: &[NodeId],
+        vars: HashMap<Box<str>, LoxValue>,
+    ) -> Result<(), Unwind> {
+        let scope = Scope::new(self.scope.clone());
+        *scope.vars.borrow_mut() = vars;
+        let enclosing = self.scope.replace(scope);
+        let executed = statements
+            .iter()
+            .try_for_each(|statement| self.visit_expr(ast, *statement));
+        self.scope = enclosing;
+        executed
+    }
+
+    /// Calls a function written in Lox, its parameters being variables of a scope nested in
+    /// the one it was declared in
+    fn call(&mut self, fun: &LoxFunction, args: Vec<LoxValue>) -> Result<LoxValue, LoxError> {
+        let declaration = fun.declaration();
+        if args.len() != declaration.params.len() {
+            return Err(LoxError::new(format!(
+                "Function {} takes {} argument(s) but was given {}",
+                fun,
+                declaration.params.len(),
+                args.len()
+            )));
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(LoxError::new(format!(
+                "Stack overflow, with {} calls in progress",
+                self.depth
+            )));
+        }
+        let Expr::Block(statements) = &fun.ast[declaration.body].expr else {
+            unreachable!("the body of a function is a block")
+        };
+        let params = declaration.params.iter().cloned().zip(args).collect();
+        let enclosing = std::mem::replace(&mut self.scope, fun.closure.clone());
+        let caller_ast = std::mem::replace(&mut self.ast, fun.ast.clone());
+        self.depth += 1;
+        let executed = self.execute_in_scope(&fun.ast, statements, params);
+        self.depth -= 1;
+        self.ast = caller_ast;
+        self.scope = enclosing;
+        match executed {
+            Ok(()) => Ok(Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err),
+            Err(Unwind::Break | Unwind::Continue) => Err(outside_loop()),
+        }
+    }
+
+    /// Declares a variable in the innermost scope, or as a global outside of blocks and
+    /// functions. Declaring a name again replaces its variable.
+    fn declare(&mut self, name: &str, value: LoxValue) {
+        match &self.scope {
+            Some(scope) => {
+                scope.vars.borrow_mut().insert(name.into(), value);
+            }
+            None => self.define(name, value),
+        }
+    }
+
+    /// Current value of an assignment target
+    fn read(&self, place: &Place) -> Result<LoxValue, LoxError> {
+        match place {
+            Place::Variable(name) => {
+                let mut scope = self.scope.as_deref();
+                while let Some(current) = scope {
+                    if let Some(value) = current.vars.borrow().get(*name) {
+                        return Ok(value.clone());
+                    }
+                    scope = current.enclosing.as_deref();
                 }
+                self.globals
+                    .get(*name)
+                    .cloned()
+                    .ok_or_else(|| undefined(name))
             }
-            (Ok(i1), BinaryOp::Ge, Ok(i2)) => {
-                if let (Int(n1), Int(n2)) = (i1, i2) {
-                    Ok(Bool(n1 > n2))
-                } else {
-                    Err(LoxError {
-                        msg: "Comparison should be between numbers only".to_string(),
-                    })
+            Place::Element { indexed, index } => element(indexed, index),
+        }
+    }
+
+    /// Stores the new value of an assignment target, which must already exist if it is a
+    /// variable
+    fn write(&mut self, place: &Place, value: LoxValue) -> Result<(), LoxError> {
+        match place {
+            Place::Variable(name) => {
+                let mut scope = self.scope.as_deref();
+                while let Some(current) = scope {
+                    if let Some(slot) = current.vars.borrow_mut().get_mut(*name) {
+                        *slot = value;
+                        return Ok(());
+                    }
+                    scope = current.enclosing.as_deref();
+                }
+                match self.globals.get_mut(*name) {
+                    Some(slot) => {
+                        *slot = value;
+                        Ok(())
+                    }
+                    None => Err(undefined(name)),
                 }
             }
-            (Ok(i1), BinaryOp::Geq, Ok(i2)) => {
-                if let (Int(n1), Int(n2)) = (i1, i2) {
-                    Ok(Bool(n1 > n2))
-                } else {
-                    Err(LoxError {
-                        msg: "Comparison should be between numbers only".to_string(),
-                    })
+            Place::Element { indexed, index } => set_element(indexed, index, value),
+        }
+    }
+
+    /// Value of the condition of a conditional, an `if` or a loop, which must be a boolean
+    fn condition(&mut self, ast: &Ast, cond: NodeId) -> Result<bool, Unwind> {
+        match self.eval(ast, cond)? {
+            Bool(b) => Ok(b),
+            _ => {
+                let (start, end) = (ast[cond].start, ast[cond].end);
+                Err(LoxError::new("Condition should be a boolean")
+                    .at(start, end)
+                    .into())
+            }
+        }
+    }
+
+    /// Whether the value matches one of the alternative patterns, literals being compared as
+    /// with `==`. A class pattern fails, as there are no classes yet to name.
+    fn matches_any(
+        &mut self,
+        ast: &Ast,
+        value: &LoxValue,
+        patterns: &[Pattern],
+    ) -> Result<bool, Unwind> {
+        for pattern in patterns {
+            let matching = match pattern {
+                Pattern::Wildcard => true,
+                Pattern::Literal(literal) => lox_equal(value, &self.eval(ast, *literal)?),
+                Pattern::Is(class) => {
+                    // no value is a class until classes are added to the language
+                    let class_value = self.eval(ast, *class)?;
+                    let (start, end) = (ast[*class].start, ast[*class].end);
+                    return Err(LoxError::new(format!("{} is not a class", class_value))
+                        .at(start, end)
+                        .into());
                 }
+            };
+            if matching {
+                return Ok(true);
             }
-            (Ok(i1), BinaryOp::Plus, Ok(i2)) => match (i1, i2) {
-                (Int(n1), Int(n2)) => Ok(Int(n1 + n2)),
-                (Strng(n1), Strng(n2)) => Ok(Strng(format!("{}{}", &n1, &n2))),
-                _ => Err(LoxError {
-                    msg: "Addition should be between numbers or strings only".to_string(),
-                }),
+        }
+        Ok(false)
+    }
+}
+
+impl Visitor for Interpreter {
+    type Error = Unwind;
+
+    fn visit_literal(&mut self, ast: &Ast, id: NodeId) -> Result<(), Unwind> {
+        self.value = match &ast[id].expr {
+            Expr::Nil => Nil,
+            Expr::Num(x) => Int(*x),
+            Expr::Float(x) => Float(*x),
+            Expr::Str(s) => Strng(s.to_string()),
+            Expr::T => Bool(true),
+            Expr::F => Bool(false),
+            _ => unreachable!("visit_literal called on a compound expression"),
+        };
+        Ok(())
+    }
+
+    fn visit_variable(&mut self, ast: &Ast, id: NodeId, name: &str) -> Result<(), Unwind> {
+        self.value = self
+            .read(&Place::Variable(name))
+            .map_err(|err| err.at(ast[id].start, ast[id].end))?;
+        Ok(())
+    }
+
+    fn visit_unary(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        op: UnaryOp,
+        e1: NodeId,
+    ) -> Result<(), Unwind> {
+        let value = self.eval(ast, e1)?;
+        self.value = unary(op, value).map_err(|err| err.at(ast[id].start, ast[id].end))?;
+        Ok(())
+    }
+
+    fn visit_binary(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        e1: NodeId,
+        op: BinaryOp,
+        e2: NodeId,
+    ) -> Result<(), Unwind> {
+        let v1 = self.eval(ast, e1)?;
+        let v2 = self.eval(ast, e2)?;
+        self.value = binary(v1, op, v2).map_err(|err| err.at(ast[id].start, ast[id].end))?;
+        Ok(())
+    }
+
+    fn visit_conditional(
+        &mut self,
+        ast: &Ast,
+        _id: NodeId,
+        cond: NodeId,
+        e1: NodeId,
+        e2: NodeId,
+    ) -> Result<(), Unwind> {
+        match self.condition(ast, cond)? {
+            true => self.visit_expr(ast, e1),
+            false => self.visit_expr(ast, e2),
+        }
+    }
+
+    fn visit_comma(
+        &mut self,
+        ast: &Ast,
+        _id: NodeId,
+        e1: NodeId,
+        e2: NodeId,
+    ) -> Result<(), Unwind> {
+        self.visit_expr(ast, e1)?;
+        self.visit_expr(ast, e2)
+    }
+
+    fn visit_match(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        subject: NodeId,
+        arms: &[MatchArm],
+    ) -> Result<(), Unwind> {
+        let value = self.eval(ast, subject)?;
+        for arm in arms {
+            if !self.matches_any(ast, &value, &arm.patterns)? {
+                continue;
+            }
+            let guarded = match arm.guard {
+                None => true,
+                Some(guard) => match self.eval(ast, guard)? {
+                    Bool(b) => b,
+                    _ => {
+                        let (start, end) = (ast[guard].start, ast[guard].end);
+                        return Err(LoxError::new("Guard should be a boolean")
+                            .at(start, end)
+                            .into());
+                    }
+                },
+            };
+            if guarded {
+                return self.visit_expr(ast, arm.body);
+            }
+        }
+        let (start, end) = (ast[id].start, ast[id].end);
+        Err(
+            LoxError::new(format!("No arm of the match matches {:?}", value))
+                .at(start, end)
+                .into(),
+        )
+    }
+
+    fn visit_list(&mut self, ast: &Ast, _id: NodeId, elements: &[NodeId]) -> Result<(), Unwind> {
+        let elements = elements
+            .iter()
+            .map(|element| self.eval(ast, *element))
+            .collect::<Result<_, _>>()?;
+        self.value = List(Rc::new(RefCell::new(elements)));
+        Ok(())
+    }
+
+    fn visit_index(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        e1: NodeId,
+        index: NodeId,
+    ) -> Result<(), Unwind> {
+        let indexed = self.eval(ast, e1)?;
+        let index = self.eval(ast, index)?;
+        self.value = element(&indexed, &index).map_err(|err| err.at(ast[id].start, ast[id].end))?;
+        Ok(())
+    }
+
+    fn visit_map(
+        &mut self,
+        ast: &Ast,
+        _id: NodeId,
+        entries: &[(NodeId, NodeId)],
+    ) -> Result<(), Unwind> {
+        let mut map = LoxMap::default();
+        for (key, value) in entries {
+            let (key_start, key_end) = (ast[*key].start, ast[*key].end);
+            let key = self.eval(ast, *key)?;
+            let value = self.eval(ast, *value)?;
+            map.insert(key, value)
+                .map_err(|err| err.at(key_start, key_end))?;
+        }
+        self.value = Map(Rc::new(RefCell::new(map)));
+        Ok(())
+    }
+
+    fn visit_slice(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        e1: NodeId,
+        from: Option<NodeId>,
+        to: Option<NodeId>,
+    ) -> Result<(), Unwind> {
+        let sliced = self.eval(ast, e1)?;
+        let from = from.map(|from| self.eval(ast, from)).transpose()?;
+        let to = to.map(|to| self.eval(ast, to)).transpose()?;
+        let at = |err: LoxError| err.at(ast[id].start, ast[id].end);
+        let List(list) = &sliced else {
+            let msg = format!("Only lists can be sliced, not {}", sliced.type_name());
+            return Err(at(LoxError::new(msg)).into());
+        };
+        let list = list.borrow();
+        let from = slice_bound(from, 0, list.len()).map_err(at)?;
+        let to = slice_bound(to, list.len(), list.len()).map_err(at)?;
+        let elements = list[from..to.max(from)].to_vec();
+        self.value = List(Rc::new(RefCell::new(elements)));
+        Ok(())
+    }
+
+    fn visit_method_call(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        call: &MethodCall,
+    ) -> Result<(), Unwind> {
+        let receiver = self.eval(ast, call.receiver)?;
+        let args = call
+            .args
+            .iter()
+            .map(|arg| self.eval(ast, *arg))
+            .collect::<Result<_, _>>()?;
+        self.value = method(&receiver, &call.method, args)
+            .map_err(|err| err.at(ast[id].start, ast[id].end))?;
+        Ok(())
+    }
+
+    fn visit_call(&mut self, ast: &Ast, id: NodeId, call: &Call) -> Result<(), Unwind> {
+        let callee = self.eval(ast, call.callee)?;
+        let args: Vec<LoxValue> = call
+            .args
+            .iter()
+            .map(|arg| self.eval(ast, *arg))
+            .collect::<Result<_, _>>()?;
+        let at = |err: LoxError| err.at(ast[id].start, ast[id].end);
+        self.value = match &callee {
+            NativeFunction(fun) => fun.call(&args).map_err(at)?,
+            Function(fun) => self.call(fun, args).map_err(|err| {
+                let mut err = at(err);
+                err.trace.push((fun.to_string(), ast[id].start));
+                err
+            })?,
+            _ => {
+                let msg = format!("{} is not callable", callee.type_name());
+                return Err(at(LoxError::new(msg)).into());
+            }
+        };
+        Ok(())
+    }
+
+    fn visit_assign(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        target: NodeId,
+        op: AssignOp,
+        value: Option<NodeId>,
+    ) -> Result<(), Unwind> {
+        let place = match &ast[target].expr {
+            Expr::Variable(name) => Place::Variable(name),
+            Expr::Index { e1, index } => Place::Element {
+                indexed: self.eval(ast, *e1)?,
+                index: self.eval(ast, *index)?,
             },
-            (Ok(i1), BinaryOp::BinMinus, Ok(i2)) => {
-                if let (Int(n1), Int(n2)) = (i1, i2) {
-                    Ok(Int(n1 - n2))
-                } else {
-                    Err(LoxError {
-                        msg: "Subtraction should be between numbers only".to_string(),
-                    })
-                }
+            _ => unreachable!("the parser only lets variables and elements be assigned to"),
+        };
+        let at = |err: LoxError| err.at(ast[id].start, ast[id].end);
+        let old = match op {
+            AssignOp::Set => Nil,
+            _ => self.read(&place).map_err(at)?,
+        };
+        let value = value.map(|value| self.eval(ast, value)).transpose()?;
+        let (new, result) = match (op, value) {
+            (AssignOp::Set, Some(value)) => (value.clone(), value),
+            (AssignOp::Compound(op), Some(value)) => {
+                let new = binary(old, op, value).map_err(at)?;
+                (new.clone(), new)
+            }
+            (AssignOp::PreStep(op), None) => {
+                let new = binary(old, op, Int(1)).map_err(at)?;
+                (new.clone(), new)
             }
-            (Ok(i1), BinaryOp::Times, Ok(i2)) => {
-                if let (Int(n1), Int(n2)) = (i1, i2) {
-                    Ok(Int(n1 * n2))
-                } else {
-                    Err(LoxError {
-                        msg: "Multiplication should be between numbers only".to_string(),
-                    })
+            (AssignOp::PostStep(op), None) => (binary(old.clone(), op, Int(1)).map_err(at)?, old),
+            _ => unreachable!("steps have no value, other assignments have one"),
+        };
+        // the element is looked up again, as evaluating the value may have changed the list
+        self.write(&place, new).map_err(at)?;
+        self.value = result;
+        Ok(())
+    }
+
+    fn visit_function(
+        &mut self,
+        _ast: &Ast,
+        id: NodeId,
+        function: &expr::Function,
+    ) -> Result<(), Unwind> {
+        let fun = Function(Rc::new(LoxFunction {
+            ast: self.ast.clone(),
+            id,
+            closure: self.scope.clone(),
+        }));
+        self.value = match &function.name {
+            Some(name) => {
+                self.declare(name, fun);
+                Nil
+            }
+            None => fun,
+        };
+        Ok(())
+    }
+
+    fn visit_program(
+        &mut self,
+        ast: &Ast,
+        _id: NodeId,
+        statements: &[NodeId],
+        value: Option<NodeId>,
+    ) -> Result<(), Unwind> {
+        for statement in statements {
+            self.visit_expr(ast, *statement)?;
+        }
+        self.value = match value {
+            Some(value) => self.eval(ast, value)?,
+            None => Nil,
+        };
+        Ok(())
+    }
+
+    fn visit_block(&mut self, ast: &Ast, _id: NodeId, statements: &[NodeId]) -> Result<(), Unwind> {
+        self.execute_in_scope(ast, statements, HashMap::new())?;
+        self.value = Nil;
+        Ok(())
+    }
+
+    fn visit_var(&mut self, ast: &Ast, _id: NodeId, var: &Var) -> Result<(), Unwind> {
+        let value = match var.init {
+            Some(init) => self.eval(ast, init)?,
+            None => Nil,
+        };
+        self.declare(&var.name, value);
+        Ok(())
+    }
+
+    fn visit_return(
+        &mut self,
+        ast: &Ast,
+        _id: NodeId,
+        value: Option<NodeId>,
+    ) -> Result<(), Unwind> {
+        let value = match value {
+            Some(value) => self.eval(ast, value)?,
+            None => Nil,
+        };
+        Err(Unwind::Return(value))
+    }
+
+    fn visit_if(
+        &mut self,
+        ast: &Ast,
+        _id: NodeId,
+        cond: NodeId,
+        then: NodeId,
+        otherwise: Option<NodeId>,
+    ) -> Result<(), Unwind> {
+        if self.condition(ast, cond)? {
+            self.visit_expr(ast, then)?;
+        } else if let Some(otherwise) = otherwise {
+            self.visit_expr(ast, otherwise)?;
+        }
+        self.value = Nil;
+        Ok(())
+    }
+
+    fn visit_while(
+        &mut self,
+        ast: &Ast,
+        _id: NodeId,
+        cond: NodeId,
+        body: NodeId,
+        step: Option<NodeId>,
+    ) -> Result<(), Unwind> {
+        while self.condition(ast, cond)? {
+            match self.visit_expr(ast, body) {
+                Ok(()) | Err(Unwind::Continue) => (),
+                Err(Unwind::Break) => break,
+                Err(unwind) => return Err(unwind),
+            }
+            if let Some(step) = step {
+                self.visit_expr(ast, step)?;
+            }
+        }
+        self.value = Nil;
+        Ok(())
+    }
+
+    /// Runs the body in a new scope for each element, so that closures made in one iteration
+    /// keep the variable of that iteration
+    fn visit_for_in(&mut self, ast: &Ast, _id: NodeId, for_in: &ForIn) -> Result<(), Unwind> {
+        self.visit_expr(ast, for_in.iterable)?;
+        let iterable = &ast[for_in.iterable];
+        let elements =
+            LoxIter::of(&self.value).map_err(|err| err.at(iterable.start, iterable.end))?;
+        for element in elements {
+            let vars = HashMap::from([(for_in.name.clone(), element)]);
+            match self.execute_in_scope(ast, &[for_in.body], vars) {
+                Ok(()) | Err(Unwind::Continue) => (),
+                Err(Unwind::Break) => break,
+                Err(unwind) => return Err(unwind),
+            }
+        }
+        self.value = Nil;
+        Ok(())
+    }
+
+    fn visit_break(&mut self, _ast: &Ast, _id: NodeId) -> Result<(), Unwind> {
+        Err(Unwind::Break)
+    }
+
+    fn visit_continue(&mut self, _ast: &Ast, _id: NodeId) -> Result<(), Unwind> {
+        Err(Unwind::Continue)
+    }
+}
+
+/// What an assignment changes, its list and index evaluated once and for all
+enum Place<'a> {
+    Variable(&'a str),
+    Element { indexed: LoxValue, index: LoxValue },
+}
+
+/// Error for a `break` or a `continue` that left the function or the program it was in, which
+/// only trees that were not parsed can have
+fn outside_loop() -> LoxError {
+    LoxError::new("Cannot break or continue outside of a loop")
+}
+
+fn undefined(name: &str) -> LoxError {
+    LoxError::new(format!("Undefined variable {}", name))
+}
+
+/// The element of a list, or the value of a map, at the given index
+fn element(indexed: &LoxValue, index: &LoxValue) -> Result<LoxValue, LoxError> {
+    match indexed {
+        List(list) => {
+            let list = list.borrow();
+            Ok(list[element_index(index, list.len())?].clone())
+        }
+        Map(map) => map
+            .borrow()
+            .get(index)?
+            .cloned()
+            .ok_or_else(|| missing_key(index)),
+        _ => Err(not_indexable(indexed)),
+    }
+}
+
+/// Replaces the element of a list at the given index, or sets the value of a key in a map
+fn set_element(indexed: &LoxValue, index: &LoxValue, value: LoxValue) -> Result<(), LoxError> {
+    match indexed {
+        List(list) => {
+            let mut list = list.borrow_mut();
+            let i = element_index(index, list.len())?;
+            list[i] = value;
+            Ok(())
+        }
+        Map(map) => map.borrow_mut().insert(index.clone(), value),
+        _ => Err(not_indexable(indexed)),
+    }
+}
+
+fn not_indexable(value: &LoxValue) -> LoxError {
+    LoxError::new(format!(
+        "Only lists and maps can be indexed, not {}",
+        value.type_name()
+    ))
+}
+
+fn missing_key(key: &LoxValue) -> LoxError {
+    LoxError::new(format!("Key {:?} is not in the map", key))
+}
+
+/// Position in a list of length `len` that an index refers to, negative indices counting from
+/// the end. The position may be out of bounds.
+fn resolve_index(index: &LoxValue, len: usize) -> Result<i64, LoxError> {
+    match index {
+        Int(i) if *i < 0 => Ok(i.saturating_add(len as i64)),
+        Int(i) => Ok(*i),
+        _ => Err(LoxError::new(format!(
+            "Index should be an integer, not {}",
+            index.type_name()
+        ))),
+    }
+}
+
+/// Position of the element an index refers to in a list of length `len`
+fn element_index(index: &LoxValue, len: usize) -> Result<usize, LoxError> {
+    match resolve_index(index, len)? {
+        i if (0..len as i64).contains(&i) => Ok(i as usize),
+        _ => Err(out_of_bounds(index, len)),
+    }
+}
+
+fn out_of_bounds(index: &LoxValue, len: usize) -> LoxError {
+    let Int(i) = index else {
+        unreachable!("only integer indices are checked against bounds")
+    };
+    LoxError::new(format!(
+        "Index {} is out of bounds for a list of length {}",
+        i, len
+    ))
+}
+
+/// Position a slice bound refers to, clamped to the list so that slices never fail on their
+/// bounds. A missing bound stands for `default`.
+fn slice_bound(bound: Option<LoxValue>, default: usize, len: usize) -> Result<usize, LoxError> {
+    match bound {
+        None => Ok(default),
+        Some(bound) => Ok(resolve_index(&bound, len)?.clamp(0, len as i64) as usize),
+    }
+}
+
+/// Calls a built-in method of a value
+fn method(receiver: &LoxValue, name: &str, args: Vec<LoxValue>) -> Result<LoxValue, LoxError> {
+    match receiver {
+        List(list) => list_method(list, name, args),
+        Map(map) => map_method(map, name, args),
+        _ => Err(no_method(receiver, name)),
+    }
+}
+
+fn no_method(receiver: &LoxValue, name: &str) -> LoxError {
+    LoxError::new(format!("{} has no method {}", receiver.type_name(), name))
+}
+
+fn check_arity(name: &str, arity: usize, args: &[LoxValue]) -> Result<(), LoxError> {
+    if args.len() == arity {
+        return Ok(());
+    }
+    Err(LoxError::new(format!(
+        "Method {} takes {} argument(s) but was given {}",
+        name,
+        arity,
+        args.len()
+    )))
+}
+
+fn list_method(
+    list: &Rc<RefCell<Vec<LoxValue>>>,
+    name: &str,
+    args: Vec<LoxValue>,
+) -> Result<LoxValue, LoxError> {
+    let arity = match name {
+        "len" | "pop" => 0,
+        "push" | "remove" | "contains" => 1,
+        "insert" => 2,
+        _ => return Err(no_method(&List(list.clone()), name)),
+    };
+    check_arity(name, arity, &args)?;
+    let mut args = args.into_iter();
+    let mut arg = || args.next().expect("the number of arguments was checked");
+    match name {
+        "len" => Ok(Int(list.borrow().len() as i64)),
+        "push" => {
+            list.borrow_mut().push(arg());
+            Ok(Nil)
+        }
+        "pop" => list
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| LoxError::new("Cannot pop from an empty list")),
+        "insert" => {
+            let index = arg();
+            let len = list.borrow().len();
+            // inserting at the length appends
+            let i = match resolve_index(&index, len)? {
+                i if (0..=len as i64).contains(&i) => i as usize,
+                _ => return Err(out_of_bounds(&index, len)),
+            };
+            list.borrow_mut().insert(i, arg());
+            Ok(Nil)
+        }
+        "remove" => {
+            let len = list.borrow().len();
+            let i = element_index(&arg(), len)?;
+            Ok(list.borrow_mut().remove(i))
+        }
+        "contains" => {
+            let value = arg();
+            let found = list
+                .borrow()
+                .iter()
+                .any(|element| lox_equal(element, &value));
+            Ok(Bool(found))
+        }
+        _ => unreachable!("methods without an arity were rejected"),
+    }
+}
+
+fn map_method(
+    map: &Rc<RefCell<LoxMap>>,
+    name: &str,
+    args: Vec<LoxValue>,
+) -> Result<LoxValue, LoxError> {
+    let arity = match name {
+        "len" | "keys" | "values" => 0,
+        "has" | "remove" => 1,
+        _ => return Err(no_method(&Map(map.clone()), name)),
+    };
+    check_arity(name, arity, &args)?;
+    let list = |elements| Ok(List(Rc::new(RefCell::new(elements))));
+    match (name, args.first()) {
+        ("len", _) => Ok(Int(map.borrow().len() as i64)),
+        ("keys", _) => list(map.borrow().keys()),
+        ("values", _) => list(map.borrow().values()),
+        ("has", Some(key)) => Ok(Bool(map.borrow().has(key)?)),
+        ("remove", Some(key)) => map
+            .borrow_mut()
+            .remove(key)?
+            .ok_or_else(|| missing_key(key)),
+        _ => unreachable!("methods without an arity were rejected"),
+    }
+}
+
+fn unary(op: UnaryOp, value: LoxValue) -> Result<LoxValue, LoxError> {
+    match (op, value) {
+        (UnaryOp::UnaMinus, Int(x)) => x
+            .checked_neg()
+            .map(Int)
+            .ok_or_else(|| LoxError::new("opposite overflows")),
+        (UnaryOp::UnaMinus, Float(x)) => Ok(Float(-x)),
+        (UnaryOp::UnaMinus, Bool(_)) => Err(LoxError::new("opposite of bool is undefined")),
+        (UnaryOp::UnaMinus, Strng(_)) => Err(LoxError::new("opposite of string is undefined")),
+        (
+            UnaryOp::UnaMinus,
+            value @ (List(_) | Map(_) | Range { .. } | NativeFunction(_) | Function(_)),
+        ) => Err(LoxError::new(format!(
+            "opposite of {} is undefined",
+            value.type_name()
+        ))),
+        (UnaryOp::UnaMinus, Nil) => Err(LoxError::new("opposite of nil is undefined")),
+        (UnaryOp::Not, Int(_) | Float(_)) => Err(LoxError::new("negation of number is undefined")),
+        (UnaryOp::Not, Bool(b)) => Ok(Bool(!b)),
+        (UnaryOp::Not, Strng(_)) => Err(LoxError::new("negation of string is undefined")),
+        (
+            UnaryOp::Not,
+            value @ (List(_) | Map(_) | Range { .. } | NativeFunction(_) | Function(_)),
+        ) => Err(LoxError::new(format!(
+            "negation of {} is undefined",
+            value.type_name()
+        ))),
+        (UnaryOp::Not, Nil) => Err(LoxError::new("negation of nil is undefined")),
+        (UnaryOp::BitNot, Int(x)) => Ok(Int(!x)),
+        (UnaryOp::BitNot, _) => Err(LoxError::new(
+            "bitwise complement is only defined on integers",
+        )),
+    }
+}
+
+fn binary(v1: LoxValue, op: BinaryOp, v2: LoxValue) -> Result<LoxValue, LoxError> {
+    match (v1, op, v2) {
+        (i1, BinaryOp::Equalequal, i2) => Ok(Bool(lox_equal(&i1, &i2))),
+        (i1, BinaryOp::NotEqual, i2) => Ok(Bool(!lox_equal(&i1, &i2))),
+        (i1, BinaryOp::Le, i2) => {
+            if let Some((n1, n2)) = as_floats(&i1, &i2) {
+                Ok(Bool(n1 < n2))
+            } else {
+                Err(LoxError::new("Comparison should be between numbers only"))
+            }
+        }
+        (i1, BinaryOp::Leq, i2) => {
+            if let Some((n1, n2)) = as_floats(&i1, &i2) {
+                Ok(Bool(n1 <= n2))
+            } else {
+                Err(LoxError::new("Comparison should be between numbers only"))
+            }
+        }
+        (i1, BinaryOp::Ge, i2) => {
+            if let Some((n1, n2)) = as_floats(&i1, &i2) {
+                Ok(Bool(n1 > n2))
+            } else {
+                Err(LoxError::new("Comparison should be between numbers only"))
+            }
+        }
+        (i1, BinaryOp::Geq, i2) => {
+            if let Some((n1, n2)) = as_floats(&i1, &i2) {
+                Ok(Bool(n1 >= n2))
+            } else {
+                Err(LoxError::new("Comparison should be between numbers only"))
+            }
+        }
+        (i1, BinaryOp::Plus, i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => match n1.checked_add(n2) {
+                Some(n) => Ok(Int(n)),
+                None => Err(LoxError::new("Addition overflows")),
+            },
+            (Strng(n1), Strng(n2)) => Ok(Strng(format!("{}{}", &n1, &n2))),
+            (i1, i2) => match as_floats(&i1, &i2) {
+                Some((n1, n2)) => Ok(Float(n1 + n2)),
+                None => Err(LoxError::new(
+                    "Addition should be between numbers or strings only",
+                )),
+            },
+        },
+        (i1, BinaryOp::BinMinus, i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => match n1.checked_sub(n2) {
+                Some(n) => Ok(Int(n)),
+                None => Err(LoxError::new("Subtraction overflows")),
+            },
+            (i1, i2) => match as_floats(&i1, &i2) {
+                Some((n1, n2)) => Ok(Float(n1 - n2)),
+                None => Err(LoxError::new("Subtraction should be between numbers only")),
+            },
+        },
+        (i1, BinaryOp::Times, i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => match n1.checked_mul(n2) {
+                Some(n) => Ok(Int(n)),
+                None => Err(LoxError::new("Multiplication overflows")),
+            },
+            (i1, i2) => match as_floats(&i1, &i2) {
+                Some((n1, n2)) => Ok(Float(n1 * n2)),
+                None => Err(LoxError::new(
+                    "Multiplication should be between numbers only",
+                )),
+            },
+        },
+        (i1, BinaryOp::Div, i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => match n1.checked_div(n2) {
+                Some(n) => Ok(Int(n)),
+                None => Err(LoxError::new("Division by zero or overflowing")),
+            },
+            (i1, i2) => match as_floats(&i1, &i2) {
+                Some((n1, n2)) => Ok(Float(n1 / n2)),
+                None => Err(LoxError::new("Division should be between numbers only")),
+            },
+        },
+        (i1, BinaryOp::IntDiv, i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => match floor_div_mod(n1, n2) {
+                Some((q, _)) => Ok(Int(q)),
+                None => Err(LoxError::new("Integer division by zero or overflowing")),
+            },
+            (i1, i2) => match as_floats(&i1, &i2) {
+                Some((n1, n2)) => Ok(Float((n1 / n2).floor())),
+                None => Err(LoxError::new(
+                    "Integer division should be between numbers only",
+                )),
+            },
+        },
+        (i1, BinaryOp::Mod, i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => match floor_div_mod(n1, n2) {
+                Some((_, r)) => Ok(Int(r)),
+                None => Err(LoxError::new("Modulo by zero or overflowing")),
+            },
+            (i1, i2) => match as_floats(&i1, &i2) {
+                Some((n1, n2)) => Ok(Float(n1 - n2 * (n1 / n2).floor())),
+                None => Err(LoxError::new("Modulo should be between numbers only")),
+            },
+        },
+        (i1, BinaryOp::Pow, i2) => match (i1, i2) {
+            // a negative exponent gives a fraction, which is computed in floating point
+            (Int(n1), Int(n2)) if n2 >= 0 => {
+                match u32::try_from(n2).ok().and_then(|n2| n1.checked_pow(n2)) {
+                    Some(n) => Ok(Int(n)),
+                    None => Err(LoxError::new("Exponentiation overflows")),
                 }
             }
-            (Ok(i1), BinaryOp::Div, Ok(i2)) => {
-                if let (Int(n1), Int(n2)) = (i1, i2) {
-                    Ok(Int(n1 / n2))
-                } else {
-                    Err(LoxError {
-                        msg: "Multiplication should be between numbers only".to_string(),
-                    })
+            (i1, i2) => match as_floats(&i1, &i2) {
+                Some((n1, n2)) => Ok(Float(n1.powf(n2))),
+                None => Err(LoxError::new(
+                    "Exponentiation should be between numbers only",
+                )),
+            },
+        },
+        (i1, op @ (BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor), i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => Ok(Int(match op {
+                BinaryOp::BitAnd => n1 & n2,
+                BinaryOp::BitOr => n1 | n2,
+                _ => n1 ^ n2,
+            })),
+            _ => Err(LoxError::new(format!(
+                "Operator {} should be between integers only",
+                op
+            ))),
+        },
+        (i1, op @ (BinaryOp::Shl | BinaryOp::Shr), i2) => match (i1, i2) {
+            (Int(n1), Int(n2)) => {
+                let shifted = u32::try_from(n2).ok().and_then(|n2| match op {
+                    BinaryOp::Shl => n1.checked_shl(n2),
+                    _ => n1.checked_shr(n2),
+                });
+                match shifted {
+                    Some(n) => Ok(Int(n)),
+                    None => Err(LoxError::new("Shift amount should be between 0 and 63")),
                 }
             }
+            _ => Err(LoxError::new(format!(
+                "Operator {} should be between integers only",
+                op
+            ))),
         },
+        (Int(start), op @ (BinaryOp::Range | BinaryOp::RangeInclusive), Int(end)) => Ok(Range {
+            start,
+            end,
+            inclusive: op == BinaryOp::RangeInclusive,
+        }),
+        (_, op @ (BinaryOp::Range | BinaryOp::RangeInclusive), _) => Err(LoxError::new(format!(
+            "Operator {} should be between integers only",
+            op
+        ))),
+    }
+}
+
+/// Returns both operands as floats if they are both numbers, so that mixed
+/// int/float arithmetic and comparisons can be carried out in floating point.
+fn as_floats(v1: &LoxValue, v2: &LoxValue) -> Option<(f64, f64)> {
+    let as_float = |v: &LoxValue| match v {
+        Int(n) => Some(*n as f64),
+        Float(x) => Some(*x),
+        _ => None,
+    };
+    Some((as_float(v1)?, as_float(v2)?))
+}
+
+/// Equality as seen by Lox: numbers compare by value regardless of their representation, lists
+/// element by element, and maps key by key regardless of their order.
+fn lox_equal(v1: &LoxValue, v2: &LoxValue) -> bool {
+    match (v1, v2) {
+        (List(l1), List(l2)) => {
+            Rc::ptr_eq(l1, l2) || {
+                let (l1, l2) = (l1.borrow(), l2.borrow());
+                l1.len() == l2.len() && l1.iter().zip(l2.iter()).all(|(e1, e2)| lox_equal(e1, e2))
+            }
+        }
+        (Map(m1), Map(m2)) => {
+            Rc::ptr_eq(m1, m2) || {
+                let (m1, m2) = (m1.borrow(), m2.borrow());
+                m1.len() == m2.len()
+                    && m1
+                        .iter()
+                        .all(|(key, v1)| matches!(m2.get(key), Ok(Some(v2)) if lox_equal(v1, v2)))
+            }
+        }
+        (Int(n1), Int(n2)) => n1 == n2,
+        (Int(_) | Float(_), Int(_) | Float(_)) => {
+            as_floats(v1, v2).is_some_and(|(n1, n2)| n1 == n2)
+        }
+        _ => v1 == v2,
+    }
+}
+
+/// Quotient rounded towards negative infinity and the matching remainder, which has the sign
+/// of the divisor. Returns None on division by zero or overflow.
+fn floor_div_mod(n1: i64, n2: i64) -> Option<(i64, i64)> {
+    let (q, r) = (n1.checked_div(n2)?, n1.checked_rem(n2)?);
+    if r != 0 && (r < 0) != (n2 < 0) {
+        Some((q - 1, r + n2))
+    } else {
+        Some((q, r))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::parser::parse;
+
+    /// Value of the tree as printed by `str`, or the message of the error it fails with
+    fn outcome(ast: Ast) -> Result<String, String> {
+        interpret(ast)
+            .map(|value| value.to_string())
+            .map_err(|err| err.msg)
+    }
+
+    fn run(src: &str) -> Result<String, String> {
+        outcome(parse(src).expect(src))
+    }
+
+    #[test]
+    fn integer_arithmetic_fails_instead_of_overflowing() {
+        for (src, msg) in [
+            ("9223372036854775807 + 1", "Addition overflows"),
+            ("-9223372036854775807 - 2", "Subtraction overflows"),
+            ("9223372036854775807 * 2", "Multiplication overflows"),
+            (
+                "(-9223372036854775807 - 1) / -1",
+                "Division by zero or overflowing",
+            ),
+            ("1 / 0", "Division by zero or overflowing"),
+            ("1 ~/ 0", "Integer division by zero or overflowing"),
+            ("1 % 0", "Modulo by zero or overflowing"),
+            ("-(-9223372036854775807 - 1)", "opposite overflows"),
+        ] {
+            assert_eq!(run(src), Err(msg.to_string()), "running {:?}", src);
+        }
+    }
+
+    #[test]
+    fn integer_arithmetic_reaches_the_bounds() {
+        for (src, value) in [
+            ("9223372036854775806 + 1", "9223372036854775807"),
+            ("-9223372036854775807 - 1", "-9223372036854775808"),
+            ("-4611686018427387904 * 2", "-9223372036854775808"),
+            ("-(-9223372036854775807)", "9223372036854775807"),
+            ("7 / 2", "3"),
+            ("1.0 / 0", "inf"),
+        ] {
+            assert_eq!(run(src), Ok(value.to_string()), "running {:?}", src);
+        }
+    }
+
+    #[test]
+    fn steps_and_compound_assignments_fail_instead_of_overflowing() {
+        for (src, msg) in [
+            ("[9223372036854775807][0]++", "Addition overflows"),
+            ("++[9223372036854775807][0]", "Addition overflows"),
+            ("--[-9223372036854775807 - 1][0]", "Subtraction overflows"),
+            ("[9223372036854775807][0] *= 2", "Multiplication overflows"),
+            ("[1][0] /= 0", "Division by zero or overflowing"),
+        ] {
+            assert_eq!(run(src), Err(msg.to_string()), "running {:?}", src);
+        }
+        assert_eq!(
+            run("[9223372036854775806][0]++"),
+            Ok("9223372036854775806".to_string())
+        );
+        assert_eq!(
+            run("++[9223372036854775806][0]"),
+            Ok("9223372036854775807".to_string())
+        );
+    }
+
+    #[test]
+    fn variables_are_assigned_to_in_place() {
+        for (src, value) in [
+            ("x = 5, x", "5"),
+            ("x += 1, x++, ++x, x", "4"),
+            ("x++", "1"),
+            ("--x", "0"),
+            ("x = [x], x[0] *= 3, x", "[3]"),
+        ] {
+            let mut interpreter = Interpreter::with_seed(7);
+            interpreter.define("x", LoxValue::Int(1));
+            let ast = parse(src).expect(src);
+            let outcome = interpreter
+                .interpret(ast)
+                .map(|value| value.to_string())
+                .map_err(|err| err.msg);
+            assert_eq!(outcome, Ok(value.to_string()), "running {:?}", src);
+        }
+        assert_eq!(run("y = 1"), Err("Undefined variable y".to_string()));
+        assert_eq!(run("y += 1"), Err("Undefined variable y".to_string()));
+    }
+
+    #[test]
+    fn inclusive_ranges_keep_their_end() {
+        for (src, value) in [
+            ("0..=10", "0..=10"),
+            ("0..10", "0..10"),
+            ("0..=9223372036854775807", "0..=9223372036854775807"),
+            ("len(0..=10), len(0..10), len(5..=4)", "0"),
+            ("len(0..=10)", "11"),
+            ("(0..=9) == (0..10)", "false"),
+            ("({0..=9: 1})[0..=9]", "1"),
+        ] {
+            assert_eq!(run(src), Ok(value.to_string()), "running {:?}", src);
+        }
+        assert_eq!(
+            run("len(-9223372036854775807 - 1..9223372036854775807)"),
+            Err("Range is too long to have a length".to_string())
+        );
+    }
+
+    #[test]
+    fn loops_go_over_what_values_iterate_over() {
+        let collect = |iterable: &str| {
+            run(&format!(
+                "var l = []; for (var x in {}) l.push(x); l",
+                iterable
+            ))
+        };
+        for (iterable, value) in [
+            ("[1, [2]]", "[1, [2]]"),
+            ("{1: 2, \"a\": nil}", "[1, \"a\"]"),
+            ("\"hé\"", "[\"h\", \"é\"]"),
+            ("-3..=-1", "[-3, -2, -1]"),
+            ("0..3", "[0, 1, 2]"),
+            ("3..0", "[]"),
+            ("3..3", "[]"),
+            ("3..=3", "[3]"),
+            (
+                "9223372036854775806..=9223372036854775807",
+                "[9223372036854775806, 9223372036854775807]",
+            ),
+            (
+                "-9223372036854775807 - 1..-9223372036854775807",
+                "[-9223372036854775808]",
+            ),
+        ] {
+            assert_eq!(
+                collect(iterable),
+                Ok(value.to_string()),
+                "iterating over {:?}",
+                iterable
+            );
+        }
+        assert_eq!(collect("1"), Err("int is not iterable".to_string()));
+    }
+
+    #[test]
+    fn loops_over_values_have_a_variable_per_iteration() {
+        for (src, value) in [
+            (
+                "var fs = []; for (var i in 0..3) fs.push(fun () { return i; }); \
+                 [fs[0](), fs[2]()]",
+                "[0, 2]",
+            ),
+            (
+                "var s = 0; for (var i in 0..10) { if (i % 3 == 0) continue; if (i > 7) break; s += i; } s",
+                "19",
+            ),
+            (
+                "var l = [1]; for (var x in l) if (x < 4) l.push(x + 1); l",
+                "[1, 2, 3, 4]",
+            ),
+            ("var x = 1; for (var x in 0..3) x = 7; x", "1"),
+        ] {
+            assert_eq!(run(src), Ok(value.to_string()), "running {:?}", src);
+        }
+    }
+
+    #[test]
+    fn functions_close_over_their_scope() {
+        for (src, value) in [
+            (
+                "fun make() { var n = 0; return fun () { n += 1; return n; }; } \
+                 var c = make(); c(); c()",
+                "2",
+            ),
+            (
+                "fun f(n) { return n < 2 ? n : f(n - 1) + f(n - 2); } f(10)",
+                "55",
+            ),
+            (
+                "var x = 1; fun f() { return x; } var y; { var x = 2; y = f(); } y",
+                "1",
+            ),
+            ("var add = fun (a, b) { return a + b; }; add(1, 2)", "3"),
+            ("fun f() { return; } f()", "nil"),
+            ("fun f() {} [f, fun () {}]", "[<fn f>, <fn at 1:16>]"),
+        ] {
+            assert_eq!(run(src), Ok(value.to_string()), "running {:?}", src);
+        }
+        assert_eq!(
+            run("fun f(a) {} f()"),
+            Err("Function <fn f> takes 1 argument(s) but was given 0".to_string())
+        );
+        assert_eq!(
+            run("{ var y = 1; } y"),
+            Err("Undefined variable y".to_string())
+        );
+    }
+
+    /// Class patterns are checked once an arm before them failed to match, and fail as no value
+    /// is a class yet
+    #[test]
+    fn class_patterns_fail_until_there_are_classes() {
+        assert_eq!(
+            run("match 1 { 1 => \"one\", is Point => \"point\" }"),
+            Ok("one".to_string())
+        );
+        assert_eq!(
+            run("match 2 { 1 => \"one\", is Point => \"point\" }"),
+            Err("Undefined variable Point".to_string())
+        );
+        assert_eq!(
+            run("match 2 { is len => 0 }"),
+            Err("<native fn len> is not a class".to_string())
+        );
+    }
+
+    #[test]
+    fn loops_run_their_step_after_continue() {
+        for (src, value) in [
+            (
+                "var s = 0; for (var i = 0; i < 10; i++) { if (i % 3 == 0) continue; s += i; } s",
+                "27",
+            ),
+            ("var n = 0; while (true) { if (n == 4) break; n++; } n", "4"),
+            ("var n = 0; for (;;) { n++; if (n > 2) break; } n", "3"),
+            (
+                "fun f() { for (var i = 0; ; i++) if (i * i > 50) return i; } f()",
+                "8",
+            ),
+            (
+                "var s = \"\"; if (s == \"\") s = \"a\"; else s = \"b\"; s",
+                "a",
+            ),
+        ] {
+            assert_eq!(run(src), Ok(value.to_string()), "running {:?}", src);
+        }
+        assert_eq!(
+            run("while (1) {}"),
+            Err("Condition should be a boolean".to_string())
+        );
+    }
+
+    /// Errors list the calls they went out of, and unbounded recursion fails before it
+    /// overflows the stack of a thread as large as the one scripts run on
+    #[test]
+    fn calls_are_traced_and_bounded() {
+        let err = interpret(parse("fun f() { return 1 / 0; }\nfun g() { f(); }\ng()").unwrap())
+            .unwrap_err();
+        let frames: Vec<_> = err
+            .trace
+            .iter()
+            .map(|(name, pos)| (name.as_str(), pos.line))
+            .collect();
+        assert_eq!(frames, [("<fn f>", 2), ("<fn g>", 3)]);
+
+        let running = std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| run("fun f(n) { return f(n + 1); } f(0)"))
+            .expect("the thread should start");
+        assert_eq!(
+            running.join().expect("the interpreter should not overflow"),
+            Err(format!(
+                "Stack overflow, with {} calls in progress",
+                MAX_CALL_DEPTH
+            ))
+        );
+    }
+
+    /// Trees sent through JSON run like the ones straight out of the parser, infinite floats
+    /// included
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialized_trees_run_the_same() {
+        for src in [
+            "1 + 2 * 3 - 4 ~/ 3 % 2 ** 3",
+            "1e999",
+            "-1e999 < 0 ? 1e999 - 1e999 : 0.5",
+            "\"a\\nb\" + str(1.5e-3)",
+            "[1, [2, 3], {\"k\": nil, 4: true}][1][0:1]",
+            "({1: 2})[1] += 3, [5][0]--",
+            "fun f(n) { var m = { n: 2 }; { m[n] *= 3; } return m; } [f(1), fun () {}]",
+            "match -0x10 { -16 | 2.5 if 1 < 2 => \"yes\", _ => \"no\" }",
+            "len([0, 1, 2].contains(1) ? \"ab\" : \"c\")",
+            "max(1, 2.5) == 2.5 ? 0..3 : 1 / 0",
+            "9223372036854775807 + 1",
+        ] {
+            let ast = parse(src).expect(src);
+            let json = serde_json::to_string(&ast).expect("trees serialize");
+            let deserialized: Ast = serde_json::from_str(&json)
+                .unwrap_or_else(|err| panic!("{:?} should deserialize: {}", src, err));
+            assert_eq!(outcome(deserialized), outcome(ast), "running {:?}", src);
+        }
     }
 }
@@ -0,0 +1,97 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{error::LoxError, interpreting::interpret::LoxValue, interpreting::map::LoxMap};
+
+/// Iteration over the elements of a value, as `for (var x in value)` goes over them. Lists and
+/// maps are read as the iteration goes, so elements pushed to a list while iterating are
+/// visited too.
+pub enum LoxIter {
+    List {
+        list: Rc<RefCell<Vec<LoxValue>>>,
+        next: usize,
+    },
+    /// Keys of a map, in insertion order
+    Keys {
+        map: Rc<RefCell<LoxMap>>,
+        next: usize,
+    },
+    /// Characters of a string, each as a string of its own
+    Chars { chars: Vec<char>, next: usize },
+    /// Integers up to `last` included, which can be i64::MAX. There is no next one once the
+    /// range is done.
+    Range { next: Option<i64>, last: i64 },
+}
+
+impl LoxIter {
+    /// Starts iterating over a value, which must be a list, a map, a string or a range
+    pub fn of(value: &LoxValue) -> Result<LoxIter, LoxError> {
+        match value {
+            LoxValue::List(list) => Ok(LoxIter::List {
+                list: list.clone(),
+                next: 0,
+            }),
+            LoxValue::Map(map) => Ok(LoxIter::Keys {
+                map: map.clone(),
+                next: 0,
+            }),
+            LoxValue::Strng(s) => Ok(LoxIter::Chars {
+                chars: s.chars().collect(),
+                next: 0,
+            }),
+            LoxValue::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let last = if *inclusive {
+                    Some(*end)
+                } else {
+                    end.checked_sub(1)
+                };
+                Ok(match last {
+                    Some(last) if last >= *start => LoxIter::Range {
+                        next: Some(*start),
+                        last,
+                    },
+                    _ => LoxIter::Range {
+                        next: None,
+                        last: *end,
+                    },
+                })
+            }
+            _ => Err(LoxError::new(format!(
+                "{} is not iterable",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+impl Iterator for LoxIter {
+    type Item = LoxValue;
+
+    fn next(&mut self) -> Option<LoxValue> {
+        match self {
+            LoxIter::List { list, next } => {
+                let element = list.borrow().get(*next).cloned()?;
+                *next += 1;
+                Some(element)
+            }
+            LoxIter::Keys { map, next } => {
+                let key = map.borrow().key_at(*next).cloned()?;
+                *next += 1;
+                Some(key)
+            }
+            LoxIter::Chars { chars, next } => {
+                let char = chars.get(*next)?;
+                *next += 1;
+                Some(LoxValue::Strng(char.to_string()))
+            }
+            LoxIter::Range { next, last } => {
+                let n = (*next)?;
+                *next = if n == *last { None } else { Some(n + 1) };
+                Some(LoxValue::Int(n))
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{error::LoxError, interpreting::interpret::LoxValue};
+
+/// Map of Lox, which iterates over its entries in the order their keys were first inserted.
+#[derive(Default, PartialEq)]
+pub struct LoxMap {
+    /// Keys as they were first written, with their values
+    entries: Vec<(LoxValue, LoxValue)>,
+    /// Position of each key in `entries`
+    positions: HashMap<HashKey, usize>,
+}
+
+/// Hashable form of a key. Numbers that Lox sees as equal have the same form, so that `1` and
+/// `1.0` are the same key.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum HashKey {
+    Int(i64),
+    /// Bits of a float without an integer value
+    Float(u64),
+    Bool(bool),
+    Str(String),
+    Nil,
+    Range(i64, i64, bool),
+}
+
+impl HashKey {
+    /// Numbers, strings, booleans and nil can be keys. NaN cannot, as it is equal to nothing and
+    /// could never be looked up, and neither can lists and maps, which can change, or functions.
+    /// Ranges can, as they never change.
+    fn of(key: &LoxValue) -> Result<HashKey, LoxError> {
+        match key {
+            LoxValue::Int(n) => Ok(HashKey::Int(*n)),
+            LoxValue::Float(x) if x.is_nan() => Err(LoxError::new("NaN cannot be a map key")),
+            // floats with an integer value in the range of integers are the same key as it
+            LoxValue::Float(x)
+                if x.fract() == 0.0 && (i64::MIN as f64..-(i64::MIN as f64)).contains(x) =>
+            {
+                Ok(HashKey::Int(*x as i64))
+            }
+            LoxValue::Float(x) => Ok(HashKey::Float(x.to_bits())),
+            LoxValue::Bool(b) => Ok(HashKey::Bool(*b)),
+            LoxValue::Strng(s) => Ok(HashKey::Str(s.clone())),
+            LoxValue::Nil => Ok(HashKey::Nil),
+            LoxValue::Range {
+                start,
+                end,
+                inclusive,
+            } => Ok(HashKey::Range(*start, *end, *inclusive)),
+            LoxValue::List(_)
+            | LoxValue::Map(_)
+            | LoxValue::NativeFunction(_)
+            | LoxValue::Function(_) => Err(LoxError::new(format!(
+                "{} cannot be a map key",
+                key.type_name()
+            ))),
+        }
+    }
+}
+
+impl LoxMap {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &LoxValue) -> Result<Option<&LoxValue>, LoxError> {
+        let position = self.positions.get(&HashKey::of(key)?);
+        Ok(position.map(|position| &self.entries[*position].1))
+    }
+
+    pub fn has(&self, key: &LoxValue) -> Result<bool, LoxError> {
+        Ok(self.positions.contains_key(&HashKey::of(key)?))
+    }
+
+    /// Sets the value of a key. A key already in the map keeps its place.
+    pub fn insert(&mut self, key: LoxValue, value: LoxValue) -> Result<(), LoxError> {
+        let hash_key = HashKey::of(&key)?;
+        match self.positions.get(&hash_key) {
+            Some(position) => self.entries[*position].1 = value,
+            None => {
+                self.positions.insert(hash_key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a key, giving back its value if it was in the map
+    pub fn remove(&mut self, key: &LoxValue) -> Result<Option<LoxValue>, LoxError> {
+        let Some(removed) = self.positions.remove(&HashKey::of(key)?) else {
+            return Ok(None);
+        };
+        // the entries after it move down to keep their order
+        for position in self.positions.values_mut() {
+            if *position > removed {
+                *position -= 1;
+            }
+        }
+        Ok(Some(self.entries.remove(removed).1))
+    }
+
+    /// Entries in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&LoxValue, &LoxValue)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    /// Key at the given place in insertion order
+    pub fn key_at(&self, position: usize) -> Option<&LoxValue> {
+        self.entries.get(position).map(|(key, _)| key)
+    }
+
+    pub fn keys(&self) -> Vec<LoxValue> {
+        self.entries.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn values(&self) -> Vec<LoxValue> {
+        self.entries
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+}
+
+impl fmt::Debug for LoxMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
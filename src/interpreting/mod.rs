@@ -1 +1,4 @@
 pub mod interpret;
+pub mod iterate;
+pub mod map;
+pub mod natives;
@@ -0,0 +1,277 @@
+use std::{
+    cell::Cell,
+    io::BufRead,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::LoxError,
+    interpreting::interpret::{Arity, Interpreter, LoxValue, LoxValue::*},
+};
+
+/// Defines the core library: conversions, input, assertions and math. `random()` draws from a
+/// generator started at `seed`, which `seed(n)` restarts.
+pub fn register_core(interpreter: &mut Interpreter, seed: u64) {
+    interpreter.register("clock", Arity::Exact(0), |_| {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| LoxError::new(err.to_string()))?;
+        Ok(Float(elapsed.as_secs_f64()))
+    });
+    interpreter.register("str", Arity::Exact(1), |args| {
+        Ok(Strng(args[0].to_string()))
+    });
+    interpreter.register("num", Arity::Exact(1), |args| match &args[0] {
+        Strng(s) => parse_number(s),
+        number @ (Int(_) | Float(_)) => Ok(number.clone()),
+        value => Err(expects("num", "a string", value)),
+    });
+    interpreter.register("len", Arity::Exact(1), |args| match &args[0] {
+        List(list) => Ok(Int(list.borrow().len() as i64)),
+        Map(map) => Ok(Int(map.borrow().len() as i64)),
+        Strng(s) => Ok(Int(s.chars().count() as i64)),
+        Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let len = (*end as i128 - *start as i128 + *inclusive as i128).max(0);
+            i64::try_from(len)
+                .map(Int)
+                .map_err(|_| LoxError::new("Range is too long to have a length"))
+        }
+        value => Err(LoxError::new(format!(
+            "{} has no length",
+            value.type_name()
+        ))),
+    });
+    interpreter.register("type", Arity::Exact(1), |args| {
+        Ok(Strng(args[0].type_name().to_string()))
+    });
+    interpreter.register("input", Arity::Exact(0), |_| {
+        let mut line = String::new();
+        let read = std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .map_err(|err| LoxError::new(err.to_string()))?;
+        if read == 0 {
+            return Ok(Nil);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(Strng(line))
+    });
+    interpreter.register("assert", Arity::Exact(2), |args| match &args[0] {
+        Bool(true) => Ok(Nil),
+        Bool(false) => Err(LoxError::new(format!("Assertion failed: {}", args[1]))),
+        _ => Err(LoxError::new("Condition should be a boolean")),
+    });
+
+    interpreter.register("sqrt", Arity::Exact(1), |args| {
+        Ok(Float(number("sqrt", &args[0])?.sqrt()))
+    });
+    interpreter.register("floor", Arity::Exact(1), |args| match &args[0] {
+        Int(n) => Ok(Int(*n)),
+        Float(x) => Ok(Float(x.floor())),
+        value => Err(expects("floor", "a number", value)),
+    });
+    interpreter.register("abs", Arity::Exact(1), |args| match &args[0] {
+        Int(n) => n
+            .checked_abs()
+            .map(Int)
+            .ok_or_else(|| LoxError::new("Absolute value overflows")),
+        Float(x) => Ok(Float(x.abs())),
+        value => Err(expects("abs", "a number", value)),
+    });
+    interpreter.register("min", Arity::AtLeast(1), |args| {
+        extremum("min", args, |x, best| x < best)
+    });
+    interpreter.register("max", Arity::AtLeast(1), |args| {
+        extremum("max", args, |x, best| x > best)
+    });
+
+    let state = Rc::new(Cell::new(seed));
+    let random_state = state.clone();
+    interpreter.register("random", Arity::Exact(0), move |_| {
+        Ok(Float(next_random(&random_state)))
+    });
+    interpreter.register("seed", Arity::Exact(1), move |args| match &args[0] {
+        Int(n) => {
+            state.set(*n as u64);
+            Ok(Nil)
+        }
+        value => Err(expects("seed", "an integer", value)),
+    });
+}
+
+/// Seed taken from the current time, for runs that do not pick their own
+pub fn clock_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+fn expects(name: &str, expected: &str, value: &LoxValue) -> LoxError {
+    LoxError::new(format!(
+        "{} expects {}, not {}",
+        name,
+        expected,
+        value.type_name()
+    ))
+}
+
+fn number(name: &str, value: &LoxValue) -> Result<f64, LoxError> {
+    match value {
+        Int(n) => Ok(*n as f64),
+        Float(x) => Ok(*x),
+        _ => Err(expects(name, "a number", value)),
+    }
+}
+
+/// Integer or float written in the string, with surrounding whitespace ignored
+fn parse_number(s: &str) -> Result<LoxValue, LoxError> {
+    let trimmed = s.trim();
+    if let Ok(n) = trimmed.parse() {
+        return Ok(Int(n));
+    }
+    // Rust also reads "inf" and "NaN", which are not Lox numbers
+    match trimmed.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(Float(x)),
+        _ => Err(LoxError::new(format!("Cannot convert {:?} to a number", s))),
+    }
+}
+
+/// The argument that wins against all the others, which must all be numbers. The first one wins
+/// ties, and it keeps its type.
+fn extremum(
+    name: &str,
+    args: &[LoxValue],
+    wins: fn(f64, f64) -> bool,
+) -> Result<LoxValue, LoxError> {
+    let mut best = &args[0];
+    let mut best_number = number(name, best)?;
+    for arg in &args[1..] {
+        let x = number(name, arg)?;
+        if wins(x, best_number) {
+            best = arg;
+            best_number = x;
+        }
+    }
+    Ok(best.clone())
+}
+
+/// Next number of a SplitMix64 generator, scaled into [0, 1)
+fn next_random(state: &Cell<u64>) -> f64 {
+    let next = state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+    state.set(next);
+    let mut z = next;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreting::interpret::Interpreter, parsing::parser::parse};
+
+    /// Value of the source as printed by `str`, or the message of the error it fails with
+    fn run(src: &str) -> Result<String, String> {
+        Interpreter::with_seed(7)
+            .interpret(parse(src).expect(src))
+            .map(|value| value.to_string())
+            .map_err(|err| err.msg)
+    }
+
+    #[test]
+    fn natives_check_their_arity() {
+        for (src, msg) in [
+            (
+                "clock(1)",
+                "Function clock takes 0 argument(s) but was given 1",
+            ),
+            ("len()", "Function len takes 1 argument(s) but was given 0"),
+            (
+                "assert(true)",
+                "Function assert takes 2 argument(s) but was given 1",
+            ),
+            (
+                "min()",
+                "Function min takes at least 1 argument(s) but was given 0",
+            ),
+            (
+                "seed(1, 2)",
+                "Function seed takes 1 argument(s) but was given 2",
+            ),
+        ] {
+            assert_eq!(run(src), Err(msg.to_string()), "running {:?}", src);
+        }
+    }
+
+    #[test]
+    fn num_reads_numbers_and_nothing_else() {
+        for (src, value) in [
+            ("num(\" 42 \")", "42"),
+            ("num(\"-2.5\")", "-2.5"),
+            ("num(\"1e3\")", "1000.0"),
+            ("num(\"99999999999999999999\")", "1e20"),
+            ("num(7)", "7"),
+        ] {
+            assert_eq!(run(src), Ok(value.to_string()), "running {:?}", src);
+        }
+        for (src, msg) in [
+            ("num(\"abc\")", "Cannot convert \"abc\" to a number"),
+            ("num(\"\")", "Cannot convert \"\" to a number"),
+            ("num(\"inf\")", "Cannot convert \"inf\" to a number"),
+            ("num(\"NaN\")", "Cannot convert \"NaN\" to a number"),
+            ("num(nil)", "num expects a string, not nil"),
+        ] {
+            assert_eq!(run(src), Err(msg.to_string()), "running {:?}", src);
+        }
+    }
+
+    #[test]
+    fn assert_reports_its_message() {
+        assert_eq!(run("assert(1 < 2, \"unused\")"), Ok("nil".to_string()));
+        assert_eq!(
+            run("assert(1 > 2, \"one is \" + str(1))"),
+            Err("Assertion failed: one is 1".to_string())
+        );
+        assert_eq!(
+            run("assert(1, \"not a boolean\")"),
+            Err("Condition should be a boolean".to_string())
+        );
+    }
+
+    /// The first of equal numbers wins and keeps its type, whatever the types of the others
+    #[test]
+    fn extrema_compare_integers_and_floats() {
+        for (src, value) in [
+            ("min(3, 2.5, 4)", "2.5"),
+            ("max(3, 2.5, 4)", "4"),
+            ("min(2, 2.0)", "2"),
+            ("min(2.0, 2)", "2.0"),
+            ("max(-1)", "-1"),
+            ("type(max(1, 1.5))", "float"),
+        ] {
+            assert_eq!(run(src), Ok(value.to_string()), "running {:?}", src);
+        }
+        assert_eq!(
+            run("max(1, \"2\")"),
+            Err("max expects a number, not string".to_string())
+        );
+    }
+
+    #[test]
+    fn random_repeats_after_seed() {
+        let draws = "[random(), random(), random()]";
+        let reseeded = format!("{d}, seed(42), {d} == (seed(42), {d})", d = draws);
+        assert_eq!(run(&reseeded), Ok("true".to_string()));
+        assert_eq!(run(draws), run(draws), "the same seed gives the same draws");
+        assert_ne!(
+            run(&format!("seed(1), {}", draws)),
+            run(&format!("seed(2), {}", draws))
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::borrow::Cow;
+
+use crate::{
+    error::LexingError,
+    lexing::{
+        interner::Interner,
+        lexer::Lexer,
+        source::StrSource,
+        token::{Token, TokenType},
+    },
+    position::Position,
+};
+
+/// Replacement of the text between `start` (included) and `end` (excluded) of a document.
+pub struct TextEdit {
+    pub start: Position,
+    pub end: Position,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    /// Position right after the replacement text once the edit is applied
+    fn new_end(&self) -> Position {
+        self.replacement.chars().fold(
+            self.start,
+            |pos, c| {
+                if c == '\n' {
+                    pos.brk()
+                } else {
+                    pos.bump(1)
+                }
+            },
+        )
+    }
+
+    /// Moves a position located after the edited range to where the same text sits once the
+    /// edit is applied.
+    fn shift(&self, new_end: Position, pos: Position) -> Position {
+        if pos.line == self.end.line {
+            Position {
+                line: new_end.line,
+                col: pos.col - self.end.col + new_end.col,
+            }
+        } else {
+            Position {
+                line: pos.line + new_end.line - self.end.line,
+                col: pos.col,
+            }
+        }
+    }
+}
+
+/// Updates the tokens of a document after an edit, without lexing the whole document again.
+///
+/// `old_tokens` is the full token stream of the document before the edit, as produced by
+/// `Lexer::new` (whitespace and the final EOF token included), and `new_text` the document
+/// once the edit is applied. Only the part of the document between the token before the
+/// edit and the first token boundary after the edit that is also a boundary in the old
+/// stream is scanned again. The other tokens are reused, with the positions of the ones
+/// after the edit shifted. The result is the same as lexing `new_text` from scratch.
+pub fn relex<'src>(
+    old_tokens: &[Token],
+    new_text: &'src str,
+    edit: &TextEdit,
+) -> Result<Vec<Token<'src>>, Vec<LexingError>> {
+    let new_end = edit.new_end();
+
+    // Scanning restarts one token before the first token reaching the edit, as the end of a
+    // token depends on the character following it.
+    let first_touched = old_tokens
+        .iter()
+        .position(|tok| tok.to_pos >= edit.start)
+        .unwrap_or(old_tokens.len());
+    let restart = first_touched.saturating_sub(1);
+
+    let mut tokens = Vec::with_capacity(old_tokens.len());
+    let mut offset = 0;
+    for tok in &old_tokens[..restart] {
+        tokens.push(reborrow(tok, new_text, offset, tok.from_pos, tok.to_pos));
+        offset += tok.lexeme.len();
+    }
+
+    let mut lexer = Lexer::with_source(StrSource::starting_at(new_text, offset));
+    lexer.position = old_tokens
+        .get(restart)
+        .map_or(Position::default(), |tok| tok.from_pos);
+
+    // old tokens after the edit, which can be reused once the new tokens line up with them
+    let mut reusable = old_tokens
+        .iter()
+        .skip(restart)
+        .skip_while(|tok| tok.from_pos < edit.end)
+        .peekable();
+    let mut errors = Vec::new();
+    while let Some(scanned) = lexer.scan_token() {
+        let tok = match scanned {
+            Ok(tok) => tok,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+        while reusable
+            .next_if(|old| edit.shift(new_end, old.from_pos) < tok.from_pos)
+            .is_some()
+        {}
+        let in_sync = tok.from_pos >= new_end
+            && reusable
+                .peek()
+                .is_some_and(|old| edit.shift(new_end, old.from_pos) == tok.from_pos);
+        if in_sync {
+            // the rest of the document is unchanged, and so are its lexing errors if any
+            if errors.is_empty() {
+                for old in reusable {
+                    let from_pos = edit.shift(new_end, old.from_pos);
+                    let to_pos = edit.shift(new_end, old.to_pos);
+                    tokens.push(reborrow(old, new_text, offset, from_pos, to_pos));
+                    offset += old.lexeme.len();
+                }
+            }
+            break;
+        }
+        offset += tok.lexeme.len();
+        tokens.push(tok);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // intern names in order of appearance, so symbols match those of a full lexing
+    let mut interner = Interner::default();
+    for tok in tokens
+        .iter_mut()
+        .filter(|tok| tok.typ == TokenType::IDENTIFIER)
+    {
+        tok.symbol = Some(interner.intern(&tok.lexeme));
+    }
+    Ok(tokens)
+}
+
+/// Copy of an old token whose text starts at byte `offset` of the new document
+fn reborrow<'src>(
+    tok: &Token,
+    new_text: &'src str,
+    offset: usize,
+    from_pos: Position,
+    to_pos: Position,
+) -> Token<'src> {
+    Token {
+        typ: tok.typ,
+        lexeme: Cow::Borrowed(&new_text[offset..offset + tok.lexeme.len()]),
+        symbol: None,
+        from_pos,
+        to_pos,
+        leading_trivia: Vec::new(),
+        trailing_trivia: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexing::interner::Symbol;
+
+    /// What the tests compare of a token: everything but the trivia, which is not filled in
+    type Summary = (TokenType, String, Option<Symbol>, Position, Position);
+
+    fn summary(
+        tokens: Result<Vec<Token>, Vec<LexingError>>,
+    ) -> Result<Vec<Summary>, Vec<LexingError>> {
+        tokens.map(|tokens| {
+            tokens
+                .into_iter()
+                .map(|tok| {
+                    let lexeme = tok.lexeme.into_owned();
+                    (tok.typ, lexeme, tok.symbol, tok.from_pos, tok.to_pos)
+                })
+                .collect()
+        })
+    }
+
+    /// Lexes the whole text, going on after errors like `relex` does
+    fn full_lexing(text: &str) -> Result<Vec<Token<'_>>, Vec<LexingError>> {
+        let (tokens, errors): (Vec<_>, Vec<_>) = Lexer::new(text).partition(Result::is_ok);
+        if errors.is_empty() {
+            Ok(tokens.into_iter().filter_map(Result::ok).collect())
+        } else {
+            Err(errors.into_iter().filter_map(Result::err).collect())
+        }
+    }
+
+    /// Position of every character boundary of the text, the end included
+    fn positions(text: &str) -> Vec<Position> {
+        let mut positions = vec![Position::default()];
+        for c in text.chars() {
+            let last = positions[positions.len() - 1];
+            positions.push(if c == '\n' { last.brk() } else { last.bump(1) });
+        }
+        positions
+    }
+
+    const DOCUMENTS: &[&str] = &[
+        "1 + 2",
+        "a == \"s t\" // note\nb",
+        "x /* c /* d */ */ 3.5e2\n  y_1 ** 0x1f",
+        "(\"\" + foo) ~/ 10 // end",
+    ];
+
+    /// Texts that open or close strings and comments, split or join tokens, or add lines
+    const REPLACEMENTS: &[&str] = &[
+        "", "\"", "/*", "*/", "//", "~", "\n", " ", "a", "9", ".", "=", "x\"y", "@",
+    ];
+
+    /// Every edit of every range of the documents, including the ones that fail to lex
+    #[test]
+    fn relex_matches_full_lexing() {
+        for document in DOCUMENTS {
+            let old_tokens =
+                full_lexing(document).unwrap_or_else(|_| panic!("{:?} should lex", document));
+            let chars: Vec<char> = document.chars().collect();
+            let positions = positions(document);
+            for start in 0..=chars.len() {
+                for end in start..=chars.len() {
+                    for replacement in REPLACEMENTS {
+                        let before: String = chars[..start].iter().collect();
+                        let after: String = chars[end..].iter().collect();
+                        let new_text = format!("{}{}{}", before, replacement, after);
+                        let edit = TextEdit {
+                            start: positions[start],
+                            end: positions[end],
+                            replacement: replacement.to_string(),
+                        };
+                        assert_eq!(
+                            summary(relex(&old_tokens, &new_text, &edit)),
+                            summary(full_lexing(&new_text)),
+                            "{:?} edited into {:?}",
+                            document,
+                            new_text
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::{borrow::Cow, collections::HashMap};
+
+/// Compact handle on an interned identifier. Two identifiers have the same symbol iff they
+/// have the same name.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+/// Maps identifier names to symbols. Names borrowed from the source are not copied, so
+/// interning only allocates when a new name is seen and the tables grow.
+#[derive(Default)]
+pub struct Interner<'src> {
+    symbols: HashMap<Cow<'src, str>, Symbol>,
+    names: Vec<Cow<'src, str>>,
+}
+
+impl<'src> Interner<'src> {
+    // taking the Cow itself lets borrowed names be stored without copying them
+    #[allow(clippy::ptr_arg)]
+    pub fn intern(&mut self, name: &Cow<'src, str>) -> Symbol {
+        if let Some(sym) = self.symbols.get(name.as_ref()) {
+            return *sym;
+        }
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name.clone());
+        self.symbols.insert(name.clone(), sym);
+        sym
+    }
+
+    pub fn resolve(&self, sym: Symbol) -> &str {
+        &self.names[sym.0 as usize]
+    }
+}
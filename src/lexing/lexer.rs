@@ -1,27 +1,45 @@
-use std::str::Chars;
-
-use peekmore::PeekMoreIterator;
+use std::io::BufRead;
 
 use crate::{
-    lexing::token::{TokenType::*, *},
+    error::LexingError,
+    lexing::{
+        interner::Interner,
+        source::{CharSource, ReaderSource, SourceError, StrSource},
+        token::{TokenType::*, *},
+    },
     position::Position,
 };
 
-pub struct Lexer<'a> {
-    pub source: PeekMoreIterator<Chars<'a>>,
+pub struct Lexer<'src, S: CharSource<'src> = StrSource<'src>> {
+    source: S,
     pub position: Position,
+    /// Identifiers seen so far
+    pub interner: Interner<'src>,
+    /// Error met while reading the token being scanned, reported in place of the token
+    source_error: Option<LexingError>,
+    /// Whether the EOF token has been emitted
+    done: bool,
 }
 
-impl Lexer<'_> {
+impl<'src> Lexer<'src> {
+    pub fn new(source: &'src str) -> Lexer<'src> {
+        Lexer::with_source(StrSource::new(source))
+    }
+
     pub fn identify_keyword(s: &str) -> Option<TokenType> {
         match s {
             "and" => Some(AND),
+            "break" => Some(BREAK),
             "class" => Some(CLASS),
+            "continue" => Some(CONTINUE),
             "else" => Some(ELSE),
             "false" => Some(FALSE),
             "for" => Some(FOR),
             "fun" => Some(FUN),
             "if" => Some(IF),
+            "in" => Some(IN),
+            "is" => Some(IS),
+            "match" => Some(MATCH),
             "nil" => Some(NIL),
             "or" => Some(OR),
             "print" => Some(PRINT),
@@ -34,12 +52,47 @@ impl Lexer<'_> {
             _ => None,
         }
     }
+}
+
+impl<R: BufRead> Lexer<'static, ReaderSource<R>> {
+    /// Lexes incrementally from a reader, such as a pipe or a large file. Lexemes are copied
+    /// out of the reader, so the tokens do not borrow anything.
+    pub fn from_reader(reader: R) -> Lexer<'static, ReaderSource<R>> {
+        Lexer::with_source(ReaderSource::new(reader))
+    }
+}
+
+impl<'src, S: CharSource<'src>> Lexer<'src, S> {
+    pub fn with_source(source: S) -> Lexer<'src, S> {
+        Lexer {
+            source,
+            position: Position::default(),
+            interner: Interner::default(),
+            source_error: None,
+            done: false,
+        }
+    }
 
     /// Gets a character from the source and returns it. Returns None iff the end has been reached.
     ///
-    /// The source is updated so that the subsequent calls return the next characters.
-    fn next(&mut self) -> Option<char> {
-        let char = self.source.next()?;
+    /// The source is updated so that the subsequent calls return the next characters. A
+    /// character that cannot be read is returned as U+FFFD, and the error is kept to be
+    /// reported in place of the current token.
+    fn advance(&mut self) -> Option<char> {
+        let char = match self.source.next_char()? {
+            Ok(char) => char,
+            Err(err) => {
+                let pos = self.position;
+                self.source_error.get_or_insert(match err {
+                    SourceError::InvalidUtf8 => LexingError::InvalidUtf8 { pos },
+                    SourceError::Io(err) => LexingError::ReadFailed {
+                        pos,
+                        msg: err.to_string(),
+                    },
+                });
+                char::REPLACEMENT_CHARACTER
+            }
+        };
         if char == '\n' {
             self.position = self.position.brk()
         } else {
@@ -49,63 +102,128 @@ impl Lexer<'_> {
     }
 
     fn peek(&mut self) -> Option<char> {
-        self.source.peek().copied()
+        self.source.peek_nth(0)
     }
 
     fn match_peek(&mut self, expected: char) -> bool {
         let matching = self.peek() == Some(expected);
         if matching {
-            let _ = self.next(); //force consumption if match
+            let _ = self.advance(); //force consumption if match
         }
         matching
     }
 
     pub fn is_done(&mut self) -> bool {
-        self.source.peek().is_none()
+        self.peek().is_none()
     }
 
-    pub fn scan_token(&mut self) -> Option<Token> {
+    /// Scans the next token from the source. Once the source is exhausted, a single EOF token
+    /// positioned at the end of the source is returned, then None.
+    pub fn scan_token(&mut self) -> Option<Result<Token<'src>, LexingError>> {
         let init_pos = self.position;
+        self.source.start_lexeme();
 
-        let char = self.next()?;
-
-        let (token_type, lexeme) = match char {
-            '(' => (Some(LEFTPAREN), None),
-            ')' => (Some(RIGHTPAREN), None),
-            '{' => (Some(LEFTBRACE), None),
-            '}' => (Some(RIGHTBRACE), None),
-            ',' => (Some(COMMA), None),
-            '.' => (Some(DOT), None),
-            '+' => (Some(PLUS), None),
-            '-' => (Some(MINUS), None),
-            ';' => (Some(SEMICOLON), None),
-            '*' => (Some(STAR), None),
+        let Some(char) = self.advance() else {
+            if self.done {
+                return None;
+            }
+            self.done = true;
+            return Some(Ok(self.token(EOF, init_pos)));
+        };
+
+        let scanned = match char {
+            '(' => Ok(LEFTPAREN),
+            ')' => Ok(RIGHTPAREN),
+            '{' => Ok(LEFTBRACE),
+            '}' => Ok(RIGHTBRACE),
+            '[' => Ok(LEFTBRACKET),
+            ']' => Ok(RIGHTBRACKET),
+            ',' => Ok(COMMA),
+            '.' => {
+                if !self.match_peek('.') {
+                    Ok(DOT)
+                } else if self.match_peek('=') {
+                    Ok(DOTDOTEQUAL)
+                } else {
+                    Ok(DOTDOT)
+                }
+            }
+            '+' => {
+                if self.match_peek('+') {
+                    Ok(PLUSPLUS)
+                } else if self.match_peek('=') {
+                    Ok(PLUSEQUAL)
+                } else {
+                    Ok(PLUS)
+                }
+            }
+            '-' => {
+                if self.match_peek('-') {
+                    Ok(MINUSMINUS)
+                } else if self.match_peek('=') {
+                    Ok(MINUSEQUAL)
+                } else {
+                    Ok(MINUS)
+                }
+            }
+            ';' => Ok(SEMICOLON),
+            '?' => Ok(QUESTION),
+            ':' => Ok(COLON),
+            '%' => Ok(PERCENT),
+            '&' => Ok(AMPERSAND),
+            '|' => Ok(PIPE),
+            '^' => Ok(CARET),
+            // `~/` divides integers, unless the slash opens a comment as in `~/* c */ x`
+            '~' => {
+                let divides =
+                    self.peek() == Some('/') && !matches!(self.source.peek_nth(1), Some('/' | '*'));
+                if divides && self.match_peek('/') {
+                    Ok(TILDESLASH)
+                } else {
+                    Ok(TILDE)
+                }
+            }
+            '*' => {
+                if self.match_peek('*') {
+                    Ok(STARSTAR)
+                } else if self.match_peek('=') {
+                    Ok(STAREQUAL)
+                } else {
+                    Ok(STAR)
+                }
+            }
             '!' => {
                 if self.match_peek('=') {
-                    (Some(BANGEQUAL), None)
+                    Ok(BANGEQUAL)
                 } else {
-                    (Some(BANG), None)
+                    Ok(BANG)
                 }
             }
             '=' => {
                 if self.match_peek('=') {
-                    (Some(EQUALEQUAL), None)
+                    Ok(EQUALEQUAL)
+                } else if self.match_peek('>') {
+                    Ok(FATARROW)
                 } else {
-                    (Some(EQUAL), None)
+                    Ok(EQUAL)
                 }
             }
             '<' => {
                 if self.match_peek('=') {
-                    (Some(LESSEQUAL), None)
+                    Ok(LESSEQUAL)
+                } else if self.match_peek('<') {
+                    Ok(LESSLESS)
                 } else {
-                    (Some(LESS), None)
+                    Ok(LESS)
                 }
             }
             '>' => {
                 if self.match_peek('=') {
-                    (Some(GREATEREQUAL), None)
+                    Ok(GREATEREQUAL)
+                } else if self.match_peek('>') {
+                    Ok(GREATERGREATER)
                 } else {
-                    (Some(GREATER), None)
+                    Ok(GREATER)
                 }
             }
 
@@ -115,12 +233,16 @@ impl Lexer<'_> {
                     // A comment goes until the end of the line.
                     let mut next = self.peek();
                     while next != Some('\n') && next.is_some() {
-                        let _ = self.next();
+                        let _ = self.advance();
                         next = self.peek();
                     }
-                    (Some(WHITESPACE), None)
+                    Ok(WHITESPACE)
+                } else if self.match_peek('=') {
+                    Ok(SLASHEQUAL)
+                } else if self.match_peek('*') {
+                    self.block_comment(init_pos)
                 } else {
-                    (Some(SLASH), None)
+                    Ok(SLASH)
                 }
             }
 
@@ -128,96 +250,321 @@ impl Lexer<'_> {
             x if x.is_whitespace() => {
                 let mut next = self.peek();
                 while next.is_some() && next.unwrap().is_whitespace() {
-                    let _ = self.next();
+                    let _ = self.advance();
                     next = self.peek();
                 }
-                (Some(WHITESPACE), None)
+                Ok(WHITESPACE)
             }
 
-            // Numbers which may have a decimal point. '123' and '123.43' are accepted
-            // but '123.blabla' should be parsed as 123, followed by a dot, followed by blabla
-            x if x.is_ascii_digit() => {
-                let mut next = self.peek();
-                let mut buf = String::with_capacity(4);
-                buf.push(x);
-                while next.is_some() && next.unwrap().is_ascii_digit() {
-                    buf.push(next.unwrap());
-                    let _ = self.next();
-                    next = self.peek();
-                }
-                if next == Some('.')
-                    && self
-                        .source
-                        .advance_cursor()
-                        .peek()
-                        .is_some_and(|c| c.is_ascii_digit())
-                {
-                    buf.push('.');
-                    self.source.reset_cursor();
-                    let _ = self.next();
-
-                    next = self.peek();
-                    while next.is_some() && next.unwrap().is_ascii_digit() {
-                        buf.push(next.unwrap());
-                        let _ = self.next();
-                        next = self.peek();
-                    }
-                }
-                (Some(NUMBER), Some(buf))
-            }
+            // Numbers, see `Lexer::number` for the accepted forms
+            x if x.is_ascii_digit() => self.number(x, init_pos),
 
             // words, which may be keywords or identifiers
-            x if x.is_ascii_alphabetic() => {
+            x if x.is_ascii_alphabetic() || x == '_' => {
                 let mut next = self.peek();
-                let mut buf = String::new();
-                buf.push(x);
-                while next.is_some() && next.unwrap().is_ascii_alphabetic() {
-                    buf.push(next.unwrap());
-                    let _ = self.next();
+                while next.is_some_and(|c| c.is_ascii_alphabetic() || c == '_') {
+                    let _ = self.advance();
                     next = self.peek();
                 }
-                let id = Self::identify_keyword(&buf).unwrap_or(IDENTIFIER);
-                (Some(id), Some(buf))
+                Ok(Lexer::identify_keyword(self.source.lexeme()).unwrap_or(IDENTIFIER))
             }
 
             // string literals, parse until closing
             '"' => {
                 let mut next = self.peek();
-                let mut buf = String::with_capacity(10);
                 while next != Some('"') && next.is_some() {
-                    buf.push(next.unwrap());
-                    let _ = self.next();
+                    let _ = self.advance();
                     next = self.peek();
                 }
                 if next.is_some() {
-                    let _ = self.next();
-                    (Some(STRING), Some(buf))
+                    let _ = self.advance();
+                    Ok(STRING)
                 } else {
-                    println!(
-                        "Encountered unterminated string starting at position {}.",
-                        init_pos.pretty_print()
-                    );
-                    (None, None)
+                    Err(LexingError::UnterminatedString { start: init_pos })
                 }
             }
 
-            _ => {
-                print!(
-                    "Encountered unknown character at position {}.",
-                    self.position.pretty_print()
-                );
-                (None, None)
+            x => Err(LexingError::UnknownCharacter {
+                pos: init_pos,
+                char: x,
+            }),
+        };
+
+        match self.source_error.take() {
+            Some(err) => Some(Err(err)),
+            None => Some(scanned.map(|typ| self.token(typ, init_pos))),
+        }
+    }
+
+    /// Builds a token of the given type spanning from `from_pos` to the current position
+    fn token(&mut self, typ: TokenType, from_pos: Position) -> Token<'src> {
+        let lexeme = self.source.take_lexeme();
+        Token {
+            typ,
+            symbol: (typ == IDENTIFIER).then(|| self.interner.intern(&lexeme)),
+            lexeme,
+            from_pos,
+            to_pos: self.position,
+            leading_trivia: Vec::new(),
+            trailing_trivia: Vec::new(),
+        }
+    }
+
+    /// Scans the whole source in lossless mode: every significant token carries the whitespace
+    /// and comments around it as trivia, and the final EOF token holds the trivia at the end of
+    /// the source. Concatenating the leading trivia, lexeme and trailing trivia of all tokens
+    /// gives back the source byte for byte.
+    pub fn scan_lossless(&mut self) -> Result<Vec<Token<'src>>, Vec<LexingError>> {
+        let mut raw = Vec::new();
+        let mut errors = Vec::new();
+        while let Some(scanned) = self.scan_token() {
+            match scanned {
+                Ok(tok) => raw.push(tok),
+                Err(err) => errors.push(err),
             }
+        }
+        if errors.is_empty() {
+            Ok(attach_trivia(raw))
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Skips the rest of a block comment whose opening `/*` has already been consumed.
+    ///
+    /// Block comments nest, so `/* a /* b */ c */` is a single comment. Reaching the end of
+    /// the source before every opened comment is closed is reported at the outermost `/*`.
+    fn block_comment(&mut self, init_pos: Position) -> Result<TokenType, LexingError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance() {
+                Some('/') if self.match_peek('*') => depth += 1,
+                Some('*') if self.match_peek('/') => depth -= 1,
+                Some(_) => (),
+                None => return Err(LexingError::UnterminatedBlockComment { start: init_pos }),
+            }
+        }
+        Ok(WHITESPACE)
+    }
+
+    /// Scans the rest of a number literal whose first digit has already been consumed.
+    ///
+    /// Accepted forms are decimal numbers with an optional fractional part and exponent
+    /// (`12`, `1.5`, `6.02e23`, `1E-9`), and integers in hexadecimal (`0x1F`), binary (`0b101`)
+    /// or octal (`0o17`). Digits may be separated by single underscores (`1_000_000`).
+    /// '123.blabla' is still parsed as 123, followed by a dot, followed by blabla.
+    fn number(&mut self, first: char, init_pos: Position) -> Result<TokenType, LexingError> {
+        let radix = match (first, self.peek()) {
+            ('0', Some('x' | 'X')) => 16,
+            ('0', Some('b' | 'B')) => 2,
+            ('0', Some('o' | 'O')) => 8,
+            _ => 10,
         };
 
-        match token_type {
-            Some(typ) => Some(Token {
-                typ,
-                lexeme: lexeme.unwrap_or(String::new()),
-                from_pos: init_pos,
-                to_pos: self.position,
-            }),
-            None => None,
+        let result = if radix != 10 {
+            let _ = self.advance();
+            self.digits(radix, false)
+        } else {
+            self.digits(10, true).and_then(|()| {
+                if self.peek() == Some('.')
+                    && self.source.peek_nth(1).is_some_and(|c| c.is_ascii_digit())
+                {
+                    let _ = self.advance();
+                    self.digits(10, false)?;
+                }
+                if self.peek().is_some_and(|c| c == 'e' || c == 'E') {
+                    let _ = self.advance();
+                    if self.peek().is_some_and(|c| c == '+' || c == '-') {
+                        let _ = self.advance();
+                    }
+                    self.digits(10, false)?;
+                }
+                Ok(())
+            })
+        };
+
+        // a number glued to letters or digits of the wrong base is malformed as a whole
+        let result = result.and_then(|()| match self.peek() {
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' => {
+                Err(format!("unexpected character '{}' in number literal", c))
+            }
+            _ => Ok(()),
+        });
+
+        match result {
+            Ok(()) => Ok(NUMBER),
+            Err(reason) => {
+                // skip the rest of the malformed literal so lexing resumes on a clean boundary
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    let _ = self.advance();
+                }
+                Err(LexingError::MalformedNumber {
+                    start: init_pos,
+                    end: self.position,
+                    reason,
+                })
+            }
+        }
+    }
+
+    /// Consumes a run of digits in the given radix, allowing single underscores between digits.
+    /// If `started` is true, a digit has already been consumed just before.
+    fn digits(&mut self, radix: u32, started: bool) -> Result<(), String> {
+        let mut last_was_digit = started;
+        let mut any_digit = started;
+        loop {
+            match self.peek() {
+                Some(c) if c.is_digit(radix) => {
+                    last_was_digit = true;
+                    any_digit = true;
+                }
+                Some('_') if last_was_digit => last_was_digit = false,
+                Some('_') => return Err("digit separators must sit between two digits".to_string()),
+                _ => break,
+            }
+            let _ = self.advance();
+        }
+        if !any_digit {
+            Err("expected digits".to_string())
+        } else if !last_was_digit {
+            Err("number literal cannot end with a digit separator".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'src, S: CharSource<'src>> Iterator for Lexer<'src, S> {
+    type Item = Result<Token<'src>, LexingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan_token()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Leading trivia, lexeme and trailing trivia of every token, in order
+    fn concatenation(tokens: &[Token]) -> String {
+        let mut text = String::new();
+        for tok in tokens {
+            tok.leading_trivia
+                .iter()
+                .for_each(|trivia| text += &trivia.text);
+            text += &tok.lexeme;
+            tok.trailing_trivia
+                .iter()
+                .for_each(|trivia| text += &trivia.text);
+        }
+        text
+    }
+
+    const FIXTURES: &[&str] = &[
+        "",
+        "1 + 2",
+        "  \n\t// only a comment",
+        "/// doc\nx ~/ 2 // trailing\n\n/** block doc */ y",
+        "a /* multi\nline */ b\r\n  c /* two\n\nblank lines */",
+        "/* outer /* nested\n /* deeper */ */ still outer */ 1",
+        "f(\"é\", \"a\nb\") /**/ ~/* not a division */ 3 //\n",
+    ];
+
+    /// Text that is not a token, and tokens that are easy to get wrong next to it
+    const PIECES: &[&str] = &[
+        " ",
+        "\t",
+        "\n",
+        "\r\n",
+        "\n\n  ",
+        "// line\n",
+        "/// doc\n",
+        "/* b */",
+        "/**/",
+        "/** doc */",
+        "/* multi\n line */",
+        "/* a /* nested\n */ b */",
+        "1",
+        "2.5e3",
+        "0x1f",
+        "\"s t\"",
+        "\"a\nb\"",
+        "\"é\"",
+        "name",
+        "+",
+        "/",
+        "~/",
+        "~",
+        "(",
+        ")",
+        "..=",
+    ];
+
+    /// Xorshift generator, so that a failing source can be built again from the seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// Pieces glued together at random. A space keeps them apart unless the pieces are
+    /// trivia, so that the text is made of the same tokens as its pieces.
+    fn random_source(rng: &mut Rng) -> String {
+        let mut src = String::new();
+        for _ in 0..rng.below(12) {
+            let piece = PIECES[rng.below(PIECES.len())];
+            src += piece;
+            src += [" ", "", "\n"][rng.below(3)];
+            if !piece.starts_with(['/', ' ', '\t', '\n', '\r']) || piece == "/" {
+                src += " ";
+            }
+        }
+        src
+    }
+
+    #[test]
+    fn lossless_tokens_give_back_the_source() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let random = (0..2000).map(|_| random_source(&mut rng));
+        for src in FIXTURES.iter().map(|src| src.to_string()).chain(random) {
+            let tokens = Lexer::new(&src)
+                .scan_lossless()
+                .unwrap_or_else(|errors| panic!("{:?} should lex, got {:?}", src, errors));
+            assert_eq!(concatenation(&tokens), src);
+            assert_eq!(
+                tokens.last().map(|tok| tok.typ),
+                Some(EOF),
+                "lexing {:?}",
+                src
+            );
+        }
+    }
+
+    /// An unterminated comment is reported at its outermost `/*`, whatever it contains
+    #[test]
+    fn unterminated_comments_are_reported_where_they_start() {
+        for (src, start) in [
+            ("1 /* open", Position { line: 1, col: 3 }),
+            ("1\n/* a /* b */ c", Position { line: 2, col: 1 }),
+            ("/* a\n// b */ c /* d\n", Position { line: 2, col: 11 }),
+            ("x /**", Position { line: 1, col: 3 }),
+        ] {
+            let errors = Lexer::new(src).scan_lossless().err();
+            assert_eq!(
+                errors,
+                Some(vec![LexingError::UnterminatedBlockComment { start }]),
+                "lexing {:?}",
+                src
+            );
         }
     }
 }
@@ -1,2 +1,5 @@
+pub mod incremental;
+pub mod interner;
 pub mod lexer;
+pub mod source;
 pub mod token;
@@ -0,0 +1,281 @@
+use std::{borrow::Cow, collections::VecDeque, io::BufRead};
+
+/// Reasons why a character could not be read from a source
+#[derive(Debug)]
+pub enum SourceError {
+    InvalidUtf8,
+    Io(std::io::Error),
+}
+
+/// Where the lexer gets its characters from.
+///
+/// A source hands out characters one at a time with a few characters of lookahead, and keeps
+/// the text of the lexeme being scanned so that it can be turned into a token. Lexemes are
+/// borrowed from the source for the lifetime `'src` when possible.
+pub trait CharSource<'src> {
+    /// Consumes the next character. Returns None iff the end has been reached.
+    fn next_char(&mut self) -> Option<Result<char, SourceError>>;
+
+    /// Looks at the character `n` positions after the next one without consuming anything.
+    /// Characters that cannot be read are seen as U+FFFD.
+    fn peek_nth(&mut self, n: usize) -> Option<char>;
+
+    /// Starts a new lexeme at the next character.
+    fn start_lexeme(&mut self);
+
+    /// Text consumed since the lexeme was started
+    fn lexeme(&self) -> &str;
+
+    /// Text consumed since the lexeme was started, in a form that can outlive the source.
+    fn take_lexeme(&mut self) -> Cow<'src, str>;
+}
+
+/// A source over a string held in memory. Lexemes borrow from the string.
+pub struct StrSource<'src> {
+    text: &'src str,
+    /// Byte offset of the next character
+    offset: usize,
+    /// Byte offset of the lexeme being scanned
+    lexeme_start: usize,
+}
+
+impl<'src> StrSource<'src> {
+    pub fn new(text: &'src str) -> StrSource<'src> {
+        StrSource {
+            text,
+            offset: 0,
+            lexeme_start: 0,
+        }
+    }
+
+    /// Source over `text` whose first character is at byte `offset`
+    pub fn starting_at(text: &'src str, offset: usize) -> StrSource<'src> {
+        StrSource {
+            text,
+            offset,
+            lexeme_start: offset,
+        }
+    }
+}
+
+impl<'src> CharSource<'src> for StrSource<'src> {
+    fn next_char(&mut self) -> Option<Result<char, SourceError>> {
+        let char = self.text[self.offset..].chars().next()?;
+        self.offset += char.len_utf8();
+        Some(Ok(char))
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<char> {
+        self.text[self.offset..].chars().nth(n)
+    }
+
+    fn start_lexeme(&mut self) {
+        self.lexeme_start = self.offset;
+    }
+
+    fn lexeme(&self) -> &str {
+        &self.text[self.lexeme_start..self.offset]
+    }
+
+    fn take_lexeme(&mut self) -> Cow<'src, str> {
+        Cow::Borrowed(&self.text[self.lexeme_start..self.offset])
+    }
+}
+
+/// A source decoding UTF-8 incrementally from a reader, so that input of any size can be lexed
+/// with bounded memory. Lexemes are copied out of the reader's buffer.
+pub struct ReaderSource<R: BufRead> {
+    reader: R,
+    /// Characters decoded ahead of time for peeking
+    lookahead: VecDeque<Result<char, SourceError>>,
+    lexeme: String,
+    /// Set after an I/O error, from which point the source is considered exhausted
+    failed: bool,
+}
+
+impl<R: BufRead> ReaderSource<R> {
+    pub fn new(reader: R) -> ReaderSource<R> {
+        ReaderSource {
+            reader,
+            lookahead: VecDeque::new(),
+            lexeme: String::new(),
+            failed: false,
+        }
+    }
+
+    /// Looks at the next byte without consuming it. Interrupted reads are retried.
+    fn peek_byte(&mut self) -> Option<Result<u8, SourceError>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            match self.reader.fill_buf() {
+                Ok(buf) => return buf.first().map(|byte| Ok(*byte)),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(SourceError::Io(err)));
+                }
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<Result<u8, SourceError>> {
+        let byte = self.peek_byte()?;
+        if byte.is_ok() {
+            self.reader.consume(1);
+        }
+        Some(byte)
+    }
+
+    /// Decodes one character from the reader. A sequence split across two fills of the
+    /// reader's buffer is read byte by byte, so it decodes as if it were contiguous.
+    fn decode(&mut self) -> Option<Result<char, SourceError>> {
+        let first = match self.read_byte()? {
+            Ok(byte) => byte,
+            Err(err) => return Some(Err(err)),
+        };
+        let width = match first {
+            0x00..=0x7F => return Some(Ok(first as char)),
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return Some(Err(SourceError::InvalidUtf8)),
+        };
+        let mut bytes = [first, 0, 0, 0];
+        for slot in bytes.iter_mut().take(width).skip(1) {
+            // only consume continuation bytes, anything else starts the next character
+            match self.peek_byte() {
+                Some(Ok(byte)) if byte & 0xC0 == 0x80 => *slot = byte,
+                Some(Err(err)) => return Some(Err(err)),
+                _ => return Some(Err(SourceError::InvalidUtf8)),
+            }
+            self.reader.consume(1);
+        }
+        match std::str::from_utf8(&bytes[..width]) {
+            Ok(s) => s.chars().next().map(Ok),
+            Err(_) => Some(Err(SourceError::InvalidUtf8)),
+        }
+    }
+}
+
+impl<R: BufRead> CharSource<'static> for ReaderSource<R> {
+    fn next_char(&mut self) -> Option<Result<char, SourceError>> {
+        let next = match self.lookahead.pop_front() {
+            Some(next) => next,
+            None => self.decode()?,
+        };
+        if let Ok(char) = next {
+            self.lexeme.push(char);
+        }
+        Some(next)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<char> {
+        while self.lookahead.len() <= n {
+            let next = self.decode()?;
+            self.lookahead.push_back(next);
+        }
+        match &self.lookahead[n] {
+            Ok(char) => Some(*char),
+            Err(_) => Some(char::REPLACEMENT_CHARACTER),
+        }
+    }
+
+    fn start_lexeme(&mut self) {
+        self.lexeme.clear();
+    }
+
+    fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    fn take_lexeme(&mut self) -> Cow<'static, str> {
+        Cow::Owned(std::mem::take(&mut self.lexeme))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufRead, Read};
+
+    use crate::{
+        error::LexingError,
+        lexing::{lexer::Lexer, token::Token},
+        position::Position,
+    };
+
+    /// Reader handing out a single byte per fill of its buffer, so that every multi-byte
+    /// character is split across fills. Every other fill is interrupted first.
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        interrupt: bool,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.fill_buf()?.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.bytes[..n]);
+            self.consume(n);
+            Ok(n)
+        }
+    }
+
+    impl BufRead for Trickle<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            Ok(&self.bytes[..self.bytes.len().min(1)])
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.bytes = &self.bytes[amount..];
+        }
+    }
+
+    fn trickle(bytes: &[u8]) -> Vec<Result<Token<'static>, LexingError>> {
+        let reader = Trickle {
+            bytes,
+            interrupt: false,
+        };
+        Lexer::from_reader(reader).collect()
+    }
+
+    /// What the tests compare of a token
+    fn summary(tok: Token) -> (String, Position, Position) {
+        (tok.lexeme.into_owned(), tok.from_pos, tok.to_pos)
+    }
+
+    #[test]
+    fn split_characters_lex_as_from_a_string() {
+        let src = "\"é日😀\" + ß // ünïcode\n\"a\u{80}\u{7ff}\u{800}\u{ffff}\u{10000}\"";
+        let from_reader: Vec<_> = trickle(src.as_bytes())
+            .into_iter()
+            .map(|tok| tok.map(summary))
+            .collect();
+        let from_str: Vec<_> = Lexer::new(src).map(|tok| tok.map(summary)).collect();
+        assert_eq!(from_reader, from_str);
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_where_it_occurs() {
+        for (bytes, col) in [
+            (&b"1 + \xff"[..], 5),
+            (b"\"\x80\"", 2),
+            (b"12 \xe6\x97 3", 4),
+            (b"\"ab\xf0\x9f\x98\"", 4),
+            (b"x\xc0\xaf", 2),
+        ] {
+            let errors: Vec<_> = trickle(bytes).into_iter().filter_map(Result::err).collect();
+            let pos = Position { line: 1, col };
+            assert_eq!(
+                errors.first(),
+                Some(&LexingError::InvalidUtf8 { pos }),
+                "lexing {:?}",
+                bytes
+            );
+        }
+    }
+}
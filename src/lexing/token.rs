@@ -1,13 +1,18 @@
-use crate::position::Position;
+use std::borrow::Cow;
+
+use crate::{lexing::interner::Symbol, position::Position};
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TokenType {
     // Single-character tokens.
     LEFTPAREN,
     RIGHTPAREN,
     LEFTBRACE,
     RIGHTBRACE,
+    LEFTBRACKET,
+    RIGHTBRACKET,
     COMMA,
     DOT,
     MINUS,
@@ -15,6 +20,13 @@ pub enum TokenType {
     SEMICOLON,
     SLASH,
     STAR,
+    QUESTION,
+    COLON,
+    PERCENT,
+    AMPERSAND,
+    PIPE,
+    CARET,
+    TILDE,
 
     // One or two character tokens.
     BANG,
@@ -25,6 +37,19 @@ pub enum TokenType {
     GREATEREQUAL,
     LESS,
     LESSEQUAL,
+    STARSTAR,
+    TILDESLASH,
+    LESSLESS,
+    GREATERGREATER,
+    PLUSEQUAL,
+    MINUSEQUAL,
+    STAREQUAL,
+    SLASHEQUAL,
+    PLUSPLUS,
+    MINUSMINUS,
+    FATARROW,
+    DOTDOT,
+    DOTDOTEQUAL,
 
     // Literals.
     IDENTIFIER,
@@ -33,12 +58,17 @@ pub enum TokenType {
 
     // Keywords.
     AND,
+    BREAK,
     CLASS,
+    CONTINUE,
     ELSE,
     FALSE,
     FUN,
     FOR,
     IF,
+    IN,
+    IS,
+    MATCH,
     NIL,
     OR,
     PRINT,
@@ -53,10 +83,136 @@ pub enum TokenType {
     EOF,
 }
 
-#[derive(Debug)]
-pub struct Token {
+/// A token of the source. Tokens lexed from a string borrow their text from it, so scanning
+/// does not allocate per token.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Token<'src> {
     pub typ: TokenType,
-    pub lexeme: String,
+    /// Exact source text of the token
+    pub lexeme: Cow<'src, str>,
+    /// Interned name, for identifiers only
+    pub symbol: Option<Symbol>,
+    pub from_pos: Position,
+    pub to_pos: Position,
+    /// Whitespace and comments preceding the token. Only filled in lossless mode.
+    pub leading_trivia: Vec<Trivia<'src>>,
+    /// Whitespace and comments following the token up to the end of its line. Only filled in
+    /// lossless mode.
+    pub trailing_trivia: Vec<Trivia<'src>>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,
+    BlockComment,
+    /// `/// ...` and `/** ... */` comments
+    DocComment,
+}
+
+impl TriviaKind {
+    pub fn of(text: &str) -> TriviaKind {
+        let line_doc = text.starts_with("///") && !text.starts_with("////");
+        let block_doc = text.starts_with("/**") && !text.starts_with("/***") && text != "/**/";
+        if line_doc || block_doc {
+            TriviaKind::DocComment
+        } else if text.starts_with("//") {
+            TriviaKind::LineComment
+        } else if text.starts_with("/*") {
+            TriviaKind::BlockComment
+        } else {
+            TriviaKind::Whitespace
+        }
+    }
+}
+
+/// A piece of source text that is irrelevant to the parser, kept around for tooling.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Trivia<'src> {
+    pub kind: TriviaKind,
+    pub text: Cow<'src, str>,
     pub from_pos: Position,
     pub to_pos: Position,
 }
+
+impl<'src> Trivia<'src> {
+    /// Splits whitespace right after its first line break. The second half is None if the
+    /// trivia does not span several lines, or if nothing follows the line break.
+    fn split_line_end(self) -> (Trivia<'src>, Option<Trivia<'src>>) {
+        match self.text.find('\n') {
+            Some(i) if self.kind == TriviaKind::Whitespace && i + 1 < self.text.len() => {
+                let (first_text, rest_text) = match self.text {
+                    Cow::Borrowed(text) => {
+                        (Cow::Borrowed(&text[..i + 1]), Cow::Borrowed(&text[i + 1..]))
+                    }
+                    Cow::Owned(text) => (
+                        Cow::Owned(text[..i + 1].to_string()),
+                        Cow::Owned(text[i + 1..].to_string()),
+                    ),
+                };
+                let break_pos = self.from_pos.brk();
+                let rest = Trivia {
+                    kind: TriviaKind::Whitespace,
+                    text: rest_text,
+                    from_pos: break_pos,
+                    to_pos: self.to_pos,
+                };
+                let first = Trivia {
+                    kind: TriviaKind::Whitespace,
+                    text: first_text,
+                    from_pos: self.from_pos,
+                    to_pos: break_pos,
+                };
+                (first, Some(rest))
+            }
+            _ => (self, None),
+        }
+    }
+
+    /// Whether the trivia ends the line it starts on
+    fn ends_line(&self) -> bool {
+        self.kind == TriviaKind::Whitespace && self.text.contains('\n')
+    }
+}
+
+impl<'src> From<Token<'src>> for Trivia<'src> {
+    fn from(tok: Token<'src>) -> Trivia<'src> {
+        Trivia {
+            kind: TriviaKind::of(&tok.lexeme),
+            text: tok.lexeme,
+            from_pos: tok.from_pos,
+            to_pos: tok.to_pos,
+        }
+    }
+}
+
+/// Attaches trivia tokens to the significant tokens around them.
+///
+/// Trivia on the same line as a token, up to and including the line break, is trailing
+/// trivia of that token. Everything else is leading trivia of the next token, which is the
+/// final EOF token for the trivia at the end of the source.
+pub fn attach_trivia(raw: Vec<Token>) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut pending = Vec::new();
+    let mut on_token_line = false;
+    for tok in raw {
+        if tok.typ != TokenType::WHITESPACE {
+            let mut tok = tok;
+            tok.leading_trivia = std::mem::take(&mut pending);
+            tokens.push(tok);
+            on_token_line = true;
+        } else if on_token_line {
+            let trivia = Trivia::from(tok);
+            on_token_line = !trivia.ends_line();
+            let (first, rest) = trivia.split_line_end();
+            tokens.last_mut().unwrap().trailing_trivia.push(first);
+            pending.extend(rest);
+        } else {
+            pending.push(Trivia::from(tok));
+        }
+    }
+    tokens
+}
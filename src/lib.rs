@@ -0,0 +1,6 @@
+pub mod error;
+pub mod formatting;
+pub mod interpreting;
+pub mod lexing;
+pub mod parsing;
+pub mod position;
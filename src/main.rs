@@ -1,54 +1,235 @@
-#![allow(dead_code)]
-
-use peekmore::PeekMore;
+use rustlox::{error, formatting, interpreting, lexing, parsing};
 use std::{
     fs,
-    io::{BufReader, Read},
+    io::{self, BufRead, BufReader, Read},
+    process::ExitCode,
 };
 
-use crate::position::Position;
-
-mod error;
-mod interpreting;
-mod lexing;
-mod parsing;
-mod position;
+const USAGE: &str = "Usage:
+    rustlox [script]                                 run a script
+    rustlox tokens <script>                          print the tokens of a script
+    rustlox ast [--format tree|sexpr|json|source] <script>
+                                                     print the syntax tree of a script
+    rustlox fmt [--check] [--width <n>] <script>     format a script in place, or with --check
+                                                     fail if it is not formatted
 
-fn main() {
-    let mut content = String::with_capacity(1000);
-    BufReader::new(fs::File::open("loxsrc/main.lox").unwrap())
-        .read_to_string(&mut content)
-        .unwrap();
+The script defaults to loxsrc/main.lox, and \"-\" reads it from stdin. Formatting stdin
+prints the result to stdout.";
 
-    let mut lex = lexing::lexer::Lexer {
-        source: content.chars().peekmore(),
-        position: Position::default(),
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let done = match args[..] {
+        [] => run("loxsrc/main.lox"),
+        ["tokens", path] => dump_tokens(path),
+        ["ast", path] => dump_ast(path, "tree"),
+        ["ast", "--format", format, path] if ["tree", "sexpr", "json", "source"].contains(&format) => {
+            dump_ast(path, format)
+        }
+        ["fmt", ref options @ .., path] if options.len() <= 3 => match fmt_options(options) {
+            Some((check, options)) => fmt(path, check, &options),
+            None => {
+                eprintln!("{}", USAGE);
+                None
+            }
+        },
+        [path] if !["tokens", "ast", "fmt"].contains(&path) => run(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            None
+        }
     };
+    match done {
+        Some(()) => ExitCode::SUCCESS,
+        None => ExitCode::FAILURE,
+    }
+}
 
-    let tokens = std::iter::from_fn(|| lex.scan_token())
-        .filter(|x| x.typ != lexing::token::TokenType::WHITESPACE);
+/// Opens the script at `path`, or stdin for "-"
+fn open(path: &str) -> Option<Box<dyn BufRead>> {
+    if path == "-" {
+        return Some(Box::new(io::stdin().lock()));
+    }
+    match fs::File::open(path) {
+        Ok(file) => Some(Box::new(BufReader::new(file))),
+        Err(err) => {
+            eprintln!("Could not open {}: {}.", path, err);
+            None
+        }
+    }
+}
 
-    let mut parser = parsing::parser::Parser {
-        lex: tokens.peekmore(),
-    };
-    let full_expr = parser.parse();
-    match full_expr {
-        Ok(pexpr) => {
-            let interpreted = interpreting::interpret::interpret(&pexpr);
-            match interpreted {
-                Ok(res) => println!("Ran successfully and obtained value {:?}", res),
-                Err(err) => eprintln!("Interpreter failed with the following error : {}", err.msg),
-            }  
-        },
-        Err(v) => {
-            for err in v {
-                match err {
-                    error::ParsingError::EarlyEof { pos } => eprintln!("Encountered early end of file near position {}", pos),
-                    error::ParsingError::UnclosedParenthesis { open_paren_start } => eprintln!("Parenthesis opened at position {} was never closed.", open_paren_start),
-                    error::ParsingError::IllegalToken { start, end, expected } => eprintln!("Unknown expression between {} and {}. Expecting {}.", start, end, expected),
+fn report_lexing_errors(errors: Vec<error::LexingError>) {
+    for err in errors {
+        match err {
+            error::LexingError::UnterminatedString { start } => eprintln!("Encountered unterminated string starting at position {}.", start),
+            error::LexingError::UnterminatedBlockComment { start } => eprintln!("Encountered unterminated block comment starting at position {}.", start),
+            error::LexingError::UnknownCharacter { pos, char } => eprintln!("Encountered unknown character '{}' at position {}.", char, pos),
+            error::LexingError::MalformedNumber { start, end, reason } => eprintln!("Malformed number literal between {} and {}: {}.", start, end, reason),
+            error::LexingError::InvalidUtf8 { pos } => eprintln!("Encountered invalid UTF-8 at position {}.", pos),
+            error::LexingError::ReadFailed { pos, msg } => eprintln!("Could not read the source after position {}: {}.", pos, msg),
+        }
+    }
+}
+
+/// Lexes and parses the script, reporting errors and returning None if there are any.
+fn parse(path: &str) -> Option<parsing::expr::Ast> {
+    match parsing::parser::parse_lexed(lexing::lexer::Lexer::from_reader(open(path)?)) {
+        Ok((ast, warnings)) => {
+            for warning in warnings {
+                match warning {
+                    error::ParsingWarning::UnreachableArm { start, end } => eprintln!("Warning: match arm between {} and {} is unreachable, as an earlier arm matches anything.", start, end),
                 }
             }
-        },
+            Some(ast)
+        }
+        Err(error::SyntaxError::Lexing(errors)) => {
+            report_lexing_errors(errors);
+            None
+        }
+        Err(error::SyntaxError::Parsing(errors)) => {
+            report_parsing_errors(errors);
+            None
+        }
     }
+}
 
+fn report_parsing_errors(errors: Vec<error::ParsingError>) {
+    for err in errors {
+        match err {
+            error::ParsingError::EarlyEof { pos, expected } => eprintln!("Expected {} at end of file, at position {}.", expected, pos),
+            error::ParsingError::UnclosedParenthesis { open_paren_start } => eprintln!("Parenthesis opened at position {} was never closed.", open_paren_start),
+            error::ParsingError::UnfinishedConditional { question_mark } => eprintln!("Conditional expression with '?' at position {} has no ':' branch.", question_mark),
+            error::ParsingError::IllegalToken { start, end, expected } => eprintln!("Unknown expression between {} and {}. Expecting {}.", start, end, expected),
+            error::ParsingError::NumberOutOfRange { start, end } => eprintln!("Number literal between {} and {} does not fit in 64 bits.", start, end),
+            error::ParsingError::MissingLeftOperand { start, end, operator } => eprintln!("Binary operator `{}` between {} and {} is missing a left-hand operand.", operator, start, end),
+            error::ParsingError::InvalidAssignmentTarget { start, end } => eprintln!("Expression between {} and {} cannot be assigned to.", start, end),
+            error::ParsingError::FieldAccess { start, end, name } => eprintln!("Field `{}` between {} and {} cannot be read or assigned to, as values only have methods.", name, start, end),
+            error::ParsingError::OutsideLoop { start, end, keyword } => eprintln!("`{}` between {} and {} is not inside a loop.", keyword, start, end),
+            error::ParsingError::OutsideFunction { start, end } => eprintln!("`return` between {} and {} is not inside a function.", start, end),
+        }
+    }
+}
+
+/// Stack of the thread running scripts, which recursive functions need deep
+const RUN_STACK_SIZE: usize = 64 << 20;
+
+fn run(path: &str) -> Option<()> {
+    let ast = parse(path)?;
+    let running = std::thread::Builder::new().stack_size(RUN_STACK_SIZE).spawn(|| interpret(ast));
+    match running {
+        Ok(thread) => thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+        Err(err) => {
+            eprintln!("Could not start the interpreter: {}.", err);
+            None
+        }
+    }
+}
+
+/// Runs the program and reports its value, or the error it failed with
+fn interpret(ast: parsing::expr::Ast) -> Option<()> {
+    match interpreting::interpret::interpret(ast) {
+        Ok(res) => {
+            println!("Ran successfully and obtained value {:?}", res);
+            Some(())
+        }
+        Err(err) => {
+            match err.span {
+                Some((start, end)) => eprintln!("Interpreter failed between {} and {} with the following error : {}", start, end, err.msg),
+                None => eprintln!("Interpreter failed with the following error : {}", err.msg),
+            }
+            // recursion repeats the same frames, which are shown once with their count
+            let mut frames = err.trace.iter().peekable();
+            while let Some(frame) = frames.next() {
+                let mut count = 1;
+                while frames.next_if_eq(&frame).is_some() {
+                    count += 1;
+                }
+                match count {
+                    1 => eprintln!("    in {} called at {}", frame.0, frame.1),
+                    _ => eprintln!("    in {} called at {}, {} times", frame.0, frame.1, count),
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Prints the tokens of the script as they are scanned, leaving out whitespace. Lexing errors
+/// are reported once the whole script is scanned.
+fn dump_tokens(path: &str) -> Option<()> {
+    let mut lexing_errors = Vec::new();
+    for scanned in lexing::lexer::Lexer::from_reader(open(path)?) {
+        match scanned {
+            Ok(tok) if tok.typ == lexing::token::TokenType::WHITESPACE => (),
+            Ok(tok) => println!("{}-{} {:?} {:?}", tok.from_pos, tok.to_pos, tok.typ, tok.lexeme),
+            Err(err) => lexing_errors.push(err),
+        }
+    }
+    if !lexing_errors.is_empty() {
+        report_lexing_errors(lexing_errors);
+        return None;
+    }
+    Some(())
+}
+
+fn dump_ast(path: &str, format: &str) -> Option<()> {
+    let ast = parse(path)?;
+    match format {
+        "sexpr" => println!("{}", parsing::printer::sexpr(&ast)),
+        "json" => println!("{}", parsing::printer::json(&ast)),
+        "source" => println!("{}", parsing::printer::source(&ast)),
+        _ => print!("{}", parsing::printer::tree(&ast)),
+    }
+    Some(())
+}
+
+/// Reads the `--check` and `--width <n>` options of the fmt command, in any order
+fn fmt_options(args: &[&str]) -> Option<(bool, formatting::formatter::FormatOptions)> {
+    let mut check = false;
+    let mut options = formatting::formatter::FormatOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--check" if !check => check = true,
+            "--width" => options.width = args.next()?.parse().ok().filter(|width| *width > 0)?,
+            _ => return None,
+        }
+    }
+    Some((check, options))
+}
+
+/// Formats the script in place, or prints it formatted when it comes from stdin. With `check`,
+/// only reports whether the script is formatted.
+fn fmt(path: &str, check: bool, options: &formatting::formatter::FormatOptions) -> Option<()> {
+    let mut src = String::new();
+    if let Err(err) = open(path)?.read_to_string(&mut src) {
+        eprintln!("Could not read {}: {}.", path, err);
+        return None;
+    }
+    let formatted = match formatting::formatter::format(&src, options) {
+        Ok(formatted) => formatted,
+        Err(error::SyntaxError::Lexing(errors)) => {
+            report_lexing_errors(errors);
+            return None;
+        }
+        Err(error::SyntaxError::Parsing(errors)) => {
+            report_parsing_errors(errors);
+            return None;
+        }
+    };
+    if check {
+        if formatted != src {
+            eprintln!("{} is not formatted.", path);
+            return None;
+        }
+    } else if path == "-" {
+        print!("{}", formatted);
+    } else if formatted != src {
+        if let Err(err) = fs::write(path, formatted) {
+            eprintln!("Could not write {}: {}.", path, err);
+            return None;
+        }
+    }
+    Some(())
 }
@@ -1,12 +1,20 @@
+use std::{
+    fmt::Display,
+    ops::{Index, IndexMut},
+};
+
 use crate::position::Position;
 
-#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UnaryOp {
     UnaMinus,
     Not,
+    BitNot,
 }
 
-#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BinaryOp {
     Equalequal,
     NotEqual,
@@ -18,30 +26,508 @@ pub enum BinaryOp {
     BinMinus,
     Times,
     Div,
+    /// `~/`, the quotient rounded towards negative infinity
+    IntDiv,
+    /// `%`, the remainder of `~/`, which has the sign of the divisor
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    /// `..`, the integers from the left operand up to the right one, which is left out
+    Range,
+    /// `..=`, the integers from the left operand up to the right one, which is included
+    RangeInclusive,
+}
+
+/// How an assignment combines the element it replaces with the assigned value
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignOp {
+    /// `target = value`
+    Set,
+    /// `target op= value`
+    Compound(BinaryOp),
+    /// `++target` and `--target`, which give the new value
+    PreStep(BinaryOp),
+    /// `target++` and `target--`, which give the old value
+    PostStep(BinaryOp),
+}
+
+impl Display for UnaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let symbol = match self {
+            UnaryOp::UnaMinus => "-",
+            UnaryOp::Not => "!",
+            UnaryOp::BitNot => "~",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let symbol = match self {
+            BinaryOp::Equalequal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Le => "<",
+            BinaryOp::Leq => "<=",
+            BinaryOp::Ge => ">",
+            BinaryOp::Geq => ">=",
+            BinaryOp::Plus => "+",
+            BinaryOp::BinMinus => "-",
+            BinaryOp::Times => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::IntDiv => "~/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "**",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Range => "..",
+            BinaryOp::RangeInclusive => "..=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Display for AssignOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AssignOp::Set => write!(f, "="),
+            AssignOp::Compound(op) => write!(f, "{}=", op),
+            AssignOp::PreStep(op) | AssignOp::PostStep(op) => write!(f, "{}{}", op, op),
+        }
+    }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug)]
 pub enum Expr {
     Num(i64),
-    Str(String),
+    /// Literals too large for a double, such as `1e999`, are infinite
+    Float(#[cfg_attr(feature = "serde", serde(with = "float_repr"))] f64),
+    Str(Box<str>),
     T,
     F,
     Nil,
+    /// A name, which refers to the innermost variable declared with it, or else to a global such
+    /// as a native function
+    Variable(Box<str>),
 
     Unary {
         op: UnaryOp,
-        e1: Box<PositionedExpr>,
+        e1: NodeId,
     },
     Binary {
-        e1: Box<PositionedExpr>,
+        e1: NodeId,
         op: BinaryOp,
-        e2: Box<PositionedExpr>,
+        e2: NodeId,
+    },
+    /// `cond ? e1 : e2`, where only the selected branch is evaluated
+    Conditional {
+        cond: NodeId,
+        e1: NodeId,
+        e2: NodeId,
+    },
+    /// `e1, e2`, which evaluates both and gives the value of `e2`
+    Comma {
+        e1: NodeId,
+        e2: NodeId,
+    },
+    /// `match subject { pattern => body, ... }`, giving the value of the first arm that
+    /// matches the subject
+    Match {
+        subject: NodeId,
+        arms: Box<[MatchArm]>,
+    },
+    /// `[e1, e2, ...]`
+    List(Box<[NodeId]>),
+    /// `{key: value, ...}`, as pairs of a key and its value
+    Map(Box<[(NodeId, NodeId)]>),
+    /// `e1[index]`, where negative indices count from the end
+    Index {
+        e1: NodeId,
+        index: NodeId,
+    },
+    /// `e1[from:to]`, a copy of the elements between the bounds, which are both optional
+    Slice {
+        e1: NodeId,
+        from: Option<NodeId>,
+        to: Option<NodeId>,
+    },
+    /// `receiver.method(args)`, boxed to keep the other nodes small
+    MethodCall(Box<MethodCall>),
+    /// `callee(args)`, boxed like method calls
+    Call(Box<Call>),
+    /// Assignment to a variable or to an element of a list or map, which evaluates the list and
+    /// the index only once. Steps have no value.
+    Assign {
+        target: NodeId,
+        op: AssignOp,
+        value: Option<NodeId>,
+    },
+    /// `fun (params) { body }`, or `fun name(params) { body }` declaring a function at the
+    /// start of a statement. Boxed like calls.
+    Function(Box<Function>),
+
+    // Statements, which the parser only builds where a statement is expected
+
+    /// The statements of a program, then the expression ending it without a `;`, whose value is
+    /// that of the program. A program made of a single expression is that expression alone.
+    Program {
+        statements: Box<[NodeId]>,
+        value: Option<NodeId>,
+    },
+    /// `{ statements }`, whose variables only live until its end
+    Block(Box<[NodeId]>),
+    /// `var name = init;`, where the variable is nil without an initializer. Boxed like calls.
+    Var(Box<Var>),
+    /// `return value;`, which gives nil without a value
+    Return(Option<NodeId>),
+    /// `if (cond) then else otherwise`, where the `else` branch is optional
+    If {
+        cond: NodeId,
+        then: NodeId,
+        otherwise: Option<NodeId>,
     },
+    /// `while (cond) body`. A `for` loop is a `while` loop whose step runs after the body, even
+    /// when it ends with `continue`, put in a block after its initializer if it has one.
+    While {
+        cond: NodeId,
+        body: NodeId,
+        step: Option<NodeId>,
+    },
+    /// `for (var name in iterable) body`, which runs the body with a new variable for each
+    /// element. Boxed like calls.
+    ForIn(Box<ForIn>),
+    /// `break;`, which ends the innermost loop
+    Break,
+    /// `continue;`, which goes on to the step and the next iteration of the innermost loop
+    Continue,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct MethodCall {
+    pub receiver: NodeId,
+    pub method: Box<str>,
+    pub args: Box<[NodeId]>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct Call {
+    pub callee: NodeId,
+    pub args: Box<[NodeId]>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct Function {
+    /// None for a lambda
+    pub name: Option<Box<str>>,
+    pub params: Box<[Box<str>]>,
+    /// An `Expr::Block`
+    pub body: NodeId,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct Var {
+    pub name: Box<str>,
+    pub init: Option<NodeId>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct ForIn {
+    pub name: Box<str>,
+    pub iterable: NodeId,
+    pub body: NodeId,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub enum Pattern {
+    /// `_`, which matches anything
+    Wildcard,
+    /// A literal, which matches the values `==` to it
+    Literal(NodeId),
+    /// `is Class`, an `Expr::Variable` naming the class whose instances it matches
+    Is(NodeId),
+}
+
+/// `p1 | p2 if guard => body`, where the guard is optional
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
+pub struct MatchArm {
+    pub patterns: Vec<Pattern>,
+    pub guard: Option<NodeId>,
+    pub body: NodeId,
+    pub start : Position,
+    pub end : Position
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug)]
 pub struct PositionedExpr {
     pub expr : Expr,
     pub start : Position,
     pub end : Position
-}
\ No newline at end of file
+}
+
+/// Identifier of a node of an `Ast`, which side tables can use as a key
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Expression tree whose nodes all live in one arena, children being referred to by id.
+/// Nodes get their ids in the order they are built, so children come before their parents.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "UncheckedAst"))]
+#[derive(Debug, Default)]
+pub struct Ast {
+    nodes: Vec<PositionedExpr>,
+    /// Node of the whole program
+    pub root: NodeId,
+}
+
+impl Ast {
+    pub fn with_capacity(nodes : usize) -> Ast {
+        Ast { nodes: Vec::with_capacity(nodes), root: NodeId::default() }
+    }
+
+    /// Adds a node. The arena grows by a quarter of its size when full rather than doubling, so
+    /// that a large tree whose size was underestimated does not end up holding twice its nodes.
+    pub fn add(&mut self, expr : Expr, start : Position, end : Position) -> NodeId {
+        let id = NodeId(u32::try_from(self.nodes.len()).expect("more than 2^32 nodes in an AST"));
+        if self.nodes.len() == self.nodes.capacity() {
+            self.nodes.reserve_exact(self.nodes.len() / 4 + 16);
+        }
+        self.nodes.push(PositionedExpr { expr, start, end });
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Ids of the expressions directly under a node, in source order
+    pub fn children(&self, id : NodeId) -> Vec<NodeId> {
+        match &self[id].expr {
+            Expr::Num(_) | Expr::Float(_) | Expr::Str(_) | Expr::T | Expr::F | Expr::Nil | Expr::Variable(_) | Expr::Break | Expr::Continue => Vec::new(),
+            Expr::Unary { e1, .. } => vec![*e1],
+            Expr::Binary { e1, e2, .. } | Expr::Comma { e1, e2 } => vec![*e1, *e2],
+            Expr::Conditional { cond, e1, e2 } => vec![*cond, *e1, *e2],
+            Expr::Match { subject, arms } => {
+                let mut children = vec![*subject];
+                for arm in arms {
+                    children.extend(arm.patterns.iter().filter_map(|pattern| match pattern {
+                        Pattern::Wildcard => None,
+                        Pattern::Literal(node) | Pattern::Is(node) => Some(*node),
+                    }));
+                    children.extend(arm.guard);
+                    children.push(arm.body);
+                }
+                children
+            }
+            Expr::List(elements) => elements.to_vec(),
+            Expr::Map(entries) => entries.iter().flat_map(|(key, value)| [*key, *value]).collect(),
+            Expr::Index { e1, index } => vec![*e1, *index],
+            Expr::Slice { e1, from, to } => [Some(*e1), *from, *to].into_iter().flatten().collect(),
+            Expr::MethodCall(call) => std::iter::once(call.receiver).chain(call.args.iter().copied()).collect(),
+            Expr::Call(call) => std::iter::once(call.callee).chain(call.args.iter().copied()).collect(),
+            Expr::Assign { target, value, .. } => std::iter::once(*target).chain(*value).collect(),
+            Expr::Function(function) => vec![function.body],
+            Expr::Program { statements, value } => statements.iter().copied().chain(*value).collect(),
+            Expr::Block(statements) => statements.to_vec(),
+            Expr::Var(var) => var.init.into_iter().collect(),
+            Expr::Return(value) => value.iter().copied().collect(),
+            Expr::If { cond, then, otherwise } => [Some(*cond), Some(*then), *otherwise].into_iter().flatten().collect(),
+            Expr::While { cond, body, step } => [Some(*cond), *step, Some(*body)].into_iter().flatten().collect(),
+            Expr::ForIn(for_in) => vec![for_in.iterable, for_in.body],
+        }
+    }
+}
+
+/// Floats as numbers, except the infinite and NaN ones, which formats like JSON have no number
+/// for and are written as the strings "inf", "-inf" and "NaN"
+#[cfg(feature = "serde")]
+mod float_repr {
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn serialize<S : Serializer>(x : &f64, serializer : S) -> Result<S::Ok, S::Error> {
+        match *x {
+            x if x.is_finite() => serializer.serialize_f64(x),
+            x if x.is_nan() => serializer.serialize_str("NaN"),
+            x if x > 0.0 => serializer.serialize_str("inf"),
+            _ => serializer.serialize_str("-inf"),
+        }
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<f64, D::Error> {
+        deserializer.deserialize_any(FloatVisitor)
+    }
+
+    struct FloatVisitor;
+
+    impl de::Visitor<'_> for FloatVisitor {
+        type Value = f64;
+
+        fn expecting(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a number, \"inf\", \"-inf\" or \"NaN\"")
+        }
+
+        fn visit_f64<E : de::Error>(self, x : f64) -> Result<f64, E> {
+            Ok(x)
+        }
+
+        fn visit_i64<E : de::Error>(self, n : i64) -> Result<f64, E> {
+            Ok(n as f64)
+        }
+
+        fn visit_u64<E : de::Error>(self, n : u64) -> Result<f64, E> {
+            Ok(n as f64)
+        }
+
+        fn visit_str<E : de::Error>(self, s : &str) -> Result<f64, E> {
+            match s {
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                "NaN" => Ok(f64::NAN),
+                _ => Err(E::invalid_value(de::Unexpected::Str(s), &self)),
+            }
+        }
+    }
+}
+
+/// Deserialized form of an `Ast`, whose ids may not refer to earlier nodes
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct UncheckedAst {
+    nodes: Vec<PositionedExpr>,
+    root: NodeId,
+}
+
+/// Checks that every id refers to a node built before the one using it, so that the tree has
+/// no cycles and can be walked without going out of bounds. Assignments, functions and class
+/// patterns must also have the shape the parser gives them, which the passes over the tree rely
+/// on.
+#[cfg(feature = "serde")]
+impl TryFrom<UncheckedAst> for Ast {
+    type Error = String;
+
+    fn try_from(unchecked : UncheckedAst) -> Result<Ast, String> {
+        let ast = Ast { nodes: unchecked.nodes, root: unchecked.root };
+        if ast.root.index() >= ast.len() {
+            return Err(format!("root {} is not a node of the tree", ast.root.index()));
+        }
+        for index in 0..ast.len() {
+            let id = NodeId(index as u32);
+            if let Some(child) = ast.children(id).into_iter().find(|child| *child >= id) {
+                return Err(format!("node {} refers to node {}, which does not come before it", index, child.index()));
+            }
+            if let Expr::Assign { target, op, value } = &ast[id].expr {
+                if !matches!(ast[*target].expr, Expr::Variable(_) | Expr::Index { .. }) {
+                    return Err(format!("node {} assigns to node {}, which is neither a variable nor an element", index, target.index()));
+                }
+                if !crate::parsing::operators::assigns(*op) {
+                    return Err(format!("node {} is an assignment with {:?}, which has no operator", index, op));
+                }
+                let step = matches!(op, AssignOp::PreStep(_) | AssignOp::PostStep(_));
+                if step != value.is_none() {
+                    let needs = if step { "takes no value" } else { "needs a value" };
+                    return Err(format!("node {} is a `{}` assignment, which {}", index, op, needs));
+                }
+            }
+            if let Expr::Match { arms, .. } = &ast[id].expr {
+                let class = arms.iter().flat_map(|arm| &arm.patterns).find_map(|pattern| match pattern {
+                    Pattern::Is(class) if !matches!(ast[*class].expr, Expr::Variable(_)) => Some(class),
+                    _ => None,
+                });
+                if let Some(class) = class {
+                    return Err(format!("node {} matches instances of node {}, which is not a name", index, class.index()));
+                }
+            }
+            if let Expr::Function(function) = &ast[id].expr {
+                if !matches!(ast[function.body].expr, Expr::Block(_)) {
+                    return Err(format!("node {} is a function whose body is not a block", index));
+                }
+            }
+        }
+        Ok(ast)
+    }
+}
+
+impl Index<NodeId> for Ast {
+    type Output = PositionedExpr;
+
+    fn index(&self, id: NodeId) -> &PositionedExpr {
+        &self.nodes[id.index()]
+    }
+}
+
+impl IndexMut<NodeId> for Ast {
+    fn index_mut(&mut self, id: NodeId) -> &mut PositionedExpr {
+        &mut self.nodes[id.index()]
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    /// Tree of a list `[1]`, the element `[1][0]`, a value, and an assignment built by `assign`
+    fn assignment(assign : impl Fn(NodeId, NodeId, NodeId) -> Expr) -> Result<Ast, String> {
+        let mut ast = Ast::default();
+        let one = ast.add(Expr::Num(1), Position::default(), Position::default());
+        let list = ast.add(Expr::List(Box::new([one])), Position::default(), Position::default());
+        let zero = ast.add(Expr::Num(0), Position::default(), Position::default());
+        let element = ast.add(Expr::Index { e1: list, index: zero }, Position::default(), Position::default());
+        let value = ast.add(Expr::Num(2), Position::default(), Position::default());
+        ast.root = ast.add(assign(list, element, value), Position::default(), Position::default());
+        let json = serde_json::to_string(&ast).expect("trees serialize");
+        serde_json::from_str(&json).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn assignments_keep_their_shape() {
+        let set = |target, value| Expr::Assign { target, op: AssignOp::Set, value };
+        let step = |target, value| Expr::Assign { target, op: AssignOp::PostStep(BinaryOp::Plus), value };
+        assert!(assignment(|_, element, value| set(element, Some(value))).is_ok());
+        assert!(assignment(|_, element, _| step(element, None)).is_ok());
+
+        let errors = [
+            assignment(|list, _, value| set(list, Some(value))),
+            assignment(|_, element, _| set(element, None)),
+            assignment(|_, element, value| step(element, Some(value))),
+            assignment(|_, element, value| Expr::Assign { target: element, op: AssignOp::Compound(BinaryOp::Range), value: Some(value) }),
+            assignment(|_, element, _| Expr::Assign { target: element, op: AssignOp::PreStep(BinaryOp::Pow), value: None }),
+        ];
+        let expected = [
+            "assigns to node 1, which is neither a variable nor an element",
+            "`=` assignment, which needs a value",
+            "`++` assignment, which takes no value",
+            "assignment with Compound(Range), which has no operator",
+            "assignment with PreStep(Pow), which has no operator",
+        ];
+        for (error, expected) in errors.into_iter().zip(expected) {
+            assert!(error.as_ref().is_err_and(|err| err.contains(expected)), "{:?} should mention {:?}", error.map(|_| ()), expected);
+        }
+    }
+}
@@ -1,2 +1,5 @@
 pub mod expr;
+pub mod operators;
 pub mod parser;
+pub mod printer;
+pub mod visit;
@@ -0,0 +1,159 @@
+use crate::{
+    lexing::token::{TokenType, TokenType::*},
+    parsing::expr::{AssignOp, BinaryOp, UnaryOp},
+};
+
+/// Binding powers of the precedence levels, from the loosest to the tightest. They are spaced
+/// out so that new levels can be slotted in between.
+pub const SEQUENCE: u8 = 10;
+pub const ASSIGNMENT: u8 = 15;
+pub const CONDITIONAL: u8 = 20;
+/// Below arithmetic and comparisons, so that `0..n + 1` ends at `n + 1`
+pub const RANGE: u8 = 25;
+pub const EQUALITY: u8 = 30;
+pub const COMPARISON: u8 = 40;
+pub const BIT_OR: u8 = 42;
+pub const BIT_XOR: u8 = 44;
+pub const BIT_AND: u8 = 46;
+pub const SHIFT: u8 = 48;
+pub const TERM: u8 = 50;
+pub const FACTOR: u8 = 60;
+pub const UNARY: u8 = 70;
+/// Above unary operators, so that `-2 ** 2` is `-(2 ** 2)`
+pub const EXPONENT: u8 = 80;
+pub const POSTFIX: u8 = 90;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Fixity {
+    /// Before its operand, like `-x`
+    Prefix,
+    /// Between its operands, like `x + y`
+    Infix,
+    /// After its operand
+    Postfix,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+/// What the parser builds when it meets an operator
+#[derive(Debug, Clone, Copy)]
+pub enum Handler {
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    /// `?`, which is followed by a branch, a `:` and another branch
+    Conditional,
+    Comma,
+    /// `target op= value`, applying the binary operator to the target and the value
+    CompoundAssign(BinaryOp),
+    /// `target = value`
+    Assign,
+    /// `++` and `--`, before or after their target
+    Step(BinaryOp),
+    /// `[`, which is followed by an index or slice bounds and a `]`
+    Index,
+    /// `.`, which is followed by a method name and its arguments in parentheses
+    Method,
+    /// `(`, which is followed by the arguments of a call and a `)`
+    Call,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Operator {
+    pub token: TokenType,
+    pub fixity: Fixity,
+    /// The higher the binding power, the tighter the operator holds its operands
+    pub bp: u8,
+    pub assoc: Assoc,
+    pub handler: Handler,
+}
+
+const fn op(token: TokenType, fixity: Fixity, bp: u8, assoc: Assoc, handler: Handler) -> Operator {
+    Operator {
+        token,
+        fixity,
+        bp,
+        assoc,
+        handler,
+    }
+}
+
+use Assoc::*;
+use Fixity::*;
+use Handler::*;
+
+/// Every operator of the expression grammar. The parser knows nothing about operators beyond
+/// this table, so adding one only takes a new entry here.
+#[rustfmt::skip]
+pub const OPERATORS: &[Operator] = &[
+    op(COMMA,          Infix,   SEQUENCE,    Left,  Comma),
+    op(EQUAL,          Infix,   ASSIGNMENT,  Right, Assign),
+    op(PLUSEQUAL,      Infix,   ASSIGNMENT,  Right, CompoundAssign(BinaryOp::Plus)),
+    op(MINUSEQUAL,     Infix,   ASSIGNMENT,  Right, CompoundAssign(BinaryOp::BinMinus)),
+    op(STAREQUAL,      Infix,   ASSIGNMENT,  Right, CompoundAssign(BinaryOp::Times)),
+    op(SLASHEQUAL,     Infix,   ASSIGNMENT,  Right, CompoundAssign(BinaryOp::Div)),
+    op(QUESTION,       Infix,   CONDITIONAL, Right, Conditional),
+    op(DOTDOT,         Infix,   RANGE,       Left,  Binary(BinaryOp::Range)),
+    op(DOTDOTEQUAL,    Infix,   RANGE,       Left,  Binary(BinaryOp::RangeInclusive)),
+    op(BANGEQUAL,      Infix,   EQUALITY,    Left,  Binary(BinaryOp::NotEqual)),
+    op(EQUALEQUAL,     Infix,   EQUALITY,    Left,  Binary(BinaryOp::Equalequal)),
+    op(GREATER,        Infix,   COMPARISON,  Left,  Binary(BinaryOp::Ge)),
+    op(GREATEREQUAL,   Infix,   COMPARISON,  Left,  Binary(BinaryOp::Geq)),
+    op(LESS,           Infix,   COMPARISON,  Left,  Binary(BinaryOp::Le)),
+    op(LESSEQUAL,      Infix,   COMPARISON,  Left,  Binary(BinaryOp::Leq)),
+    op(PIPE,           Infix,   BIT_OR,      Left,  Binary(BinaryOp::BitOr)),
+    op(CARET,          Infix,   BIT_XOR,     Left,  Binary(BinaryOp::BitXor)),
+    op(AMPERSAND,      Infix,   BIT_AND,     Left,  Binary(BinaryOp::BitAnd)),
+    op(LESSLESS,       Infix,   SHIFT,       Left,  Binary(BinaryOp::Shl)),
+    op(GREATERGREATER, Infix,   SHIFT,       Left,  Binary(BinaryOp::Shr)),
+    op(MINUS,          Infix,   TERM,        Left,  Binary(BinaryOp::BinMinus)),
+    op(PLUS,           Infix,   TERM,        Left,  Binary(BinaryOp::Plus)),
+    op(SLASH,          Infix,   FACTOR,      Left,  Binary(BinaryOp::Div)),
+    op(TILDESLASH,     Infix,   FACTOR,      Left,  Binary(BinaryOp::IntDiv)),
+    op(PERCENT,        Infix,   FACTOR,      Left,  Binary(BinaryOp::Mod)),
+    op(STAR,           Infix,   FACTOR,      Left,  Binary(BinaryOp::Times)),
+    op(BANG,           Prefix,  UNARY,       Right, Unary(UnaryOp::Not)),
+    op(MINUS,          Prefix,  UNARY,       Right, Unary(UnaryOp::UnaMinus)),
+    op(TILDE,          Prefix,  UNARY,       Right, Unary(UnaryOp::BitNot)),
+    op(PLUSPLUS,       Prefix,  UNARY,       Right, Step(BinaryOp::Plus)),
+    op(MINUSMINUS,     Prefix,  UNARY,       Right, Step(BinaryOp::BinMinus)),
+    op(STARSTAR,       Infix,   EXPONENT,    Right, Binary(BinaryOp::Pow)),
+    op(PLUSPLUS,       Postfix, POSTFIX,     Left,  Step(BinaryOp::Plus)),
+    op(MINUSMINUS,     Postfix, POSTFIX,     Left,  Step(BinaryOp::BinMinus)),
+    op(LEFTBRACKET,    Postfix, POSTFIX,     Left,  Index),
+    op(DOT,            Postfix, POSTFIX,     Left,  Method),
+    op(LEFTPAREN,      Postfix, POSTFIX,     Left,  Call),
+];
+
+/// Finds the operator that a token of the given type stands for in the given position
+pub fn lookup(token: TokenType, fixity: Fixity) -> Option<&'static Operator> {
+    OPERATORS
+        .iter()
+        .find(|op| op.token == token && op.fixity == fixity)
+}
+
+/// Finds the infix operator that builds the given binary operation
+pub fn binary(op: BinaryOp) -> &'static Operator {
+    OPERATORS
+        .iter()
+        .find(|entry| {
+            entry.fixity == Fixity::Infix
+                && matches!(entry.handler, Handler::Binary(binary) if binary == op)
+        })
+        .expect("every binary operation has an operator")
+}
+
+/// Tells whether some operator builds assignments of the given kind. Binary operations without
+/// a compound assignment, such as `..=`, or a step, such as `**`, have no syntax.
+pub fn assigns(op: AssignOp) -> bool {
+    OPERATORS.iter().any(|entry| match (entry.handler, op) {
+        (Assign, AssignOp::Set) => true,
+        (CompoundAssign(binary), AssignOp::Compound(compound)) => binary == compound,
+        (Step(binary), AssignOp::PreStep(step)) => entry.fixity == Prefix && binary == step,
+        (Step(binary), AssignOp::PostStep(step)) => entry.fixity == Postfix && binary == step,
+        _ => false,
+    })
+}
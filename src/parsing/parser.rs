@@ -1,208 +1,856 @@
-use peekmore::PeekMoreIterator;
+use std::borrow::Cow;
 
-use crate::error::ParsingError;
+use peekmore::{PeekMore, PeekMoreIterator};
+
+use crate::error::{ParsingError, ParsingWarning, SyntaxError};
+use crate::lexing::lexer::Lexer;
+use crate::lexing::source::CharSource;
 use crate::lexing::token::{Token, TokenType, TokenType::*};
-use crate::parsing::expr::{BinaryOp, PositionedExpr};
+use crate::parsing::expr::{AssignOp, Ast, Call, ForIn, Function, MatchArm, MethodCall, NodeId, Pattern, Var};
+use crate::parsing::operators::{self, Assoc, Fixity, Handler, Operator};
 use crate::position::Position;
 
-use super::expr::{Expr, UnaryOp};
+use super::expr::Expr;
+
+type ParsingResult = Result<NodeId, Vec<ParsingError>>;
 
-type ParsingResult = Result<PositionedExpr, Vec<ParsingError>>;
+/// Lexes and parses a source held in memory. Warnings are dropped, `parse_lexed` keeps them.
+pub fn parse(src : &str) -> Result<Ast, SyntaxError> {
+    parse_lexed(Lexer::new(src)).map(|(ast, _)| ast)
+}
+
+/// Parses the tokens of the lexer as it scans them, leaving out whitespace, so that the tokens
+/// are never all held at once. Lexing errors are reported instead of the parsing errors they
+/// would cause, all of them since the lexer goes on after an error.
+pub fn parse_lexed<'src, S : CharSource<'src>>(lexer : Lexer<'src, S>) -> Result<(Ast, Vec<ParsingWarning>), SyntaxError> {
+    let mut lexing_errors = Vec::new();
+    let tokens = lexer.filter_map(|scanned| match scanned {
+        Ok(tok) if tok.typ == WHITESPACE => None,
+        Ok(tok) => Some(tok),
+        Err(err) => {
+            lexing_errors.push(err);
+            None
+        }
+    });
+    let mut parser = Parser::new(tokens);
+    let parsed = parser.parse();
+    let warnings = std::mem::take(&mut parser.warnings);
+    drop(parser);
+    match parsed {
+        _ if !lexing_errors.is_empty() => Err(SyntaxError::Lexing(lexing_errors)),
+        Ok(ast) => Ok((ast, warnings)),
+        Err(errors) => Err(SyntaxError::Parsing(errors)),
+    }
+}
 
 pub struct Parser<I: Iterator> {
-    pub lex: PeekMoreIterator<I>,
+    lex: PeekMoreIterator<I>,
+    /// Where the last token consumed ends
+    end: Position,
+    /// EOF token made up at `end` for a stream that runs out without one
+    eof: Option<I::Item>,
+    /// Errors that did not stop the parsing, reported once it is done
+    errors: Vec<ParsingError>,
+    pub warnings: Vec<ParsingWarning>,
+    /// Number of function bodies the parser is in, as `return` can only be in one
+    functions: usize,
+    /// Number of loops the parser is in within the innermost function body, as `break` and
+    /// `continue` can only be in one
+    loops: usize,
+    /// Tree being built
+    ast: Ast,
 }
 
-impl<I: Iterator<Item = Token>> Parser<I> {
-    fn next(&mut self) -> Option<Token> {
-        self.lex.next()
+impl<'src, I: Iterator<Item = Token<'src>>> Parser<I> {
+    pub fn new(tokens: I) -> Parser<I> {
+        // brackets, separators and keywords make no node, so that about half of the tokens
+        // make one in typical code. The arena grows in small steps from that estimate.
+        let ast = Ast::with_capacity(tokens.size_hint().0 / 2);
+        Parser {
+            lex: tokens.peekmore(),
+            end: Position::default(),
+            eof: None,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            functions: 0,
+            loops: 0,
+            ast,
+        }
     }
 
-    fn peek(&mut self) -> Option<&Token> {
-        self.lex.peek()
+    /// Gets the next token. The stream ends with an EOF token which is never consumed, so
+    /// that every call past the end returns it again.
+    fn next(&mut self) -> Token<'src> {
+        if self.peek().typ == EOF {
+            return self.peek().clone();
+        }
+        let tok = self.lex.next().expect("a token was peeked");
+        self.end = tok.to_pos;
+        tok
     }
 
-    fn peek_match(&mut self, types: Vec<TokenType>) -> bool {
-        match self.peek() {
-            None => false,
-            Some(tok) => types.contains(&tok.typ),
+    /// Looks at the next token. A stream that runs out without an EOF token is given one where
+    /// its last token ends, so that it is reported as ending early rather than cut short.
+    fn peek(&mut self) -> &Token<'src> {
+        if self.lex.peek().is_none() {
+            let end = self.end;
+            return self.eof.get_or_insert_with(|| Token {
+                typ: EOF,
+                lexeme: Cow::Borrowed(""),
+                symbol: None,
+                from_pos: end,
+                to_pos: end,
+                leading_trivia: Vec::new(),
+                trailing_trivia: Vec::new(),
+            });
         }
+        self.lex.peek().expect("the stream has a next token")
     }
 
-    pub fn parse(&mut self) -> ParsingResult {
-        self.expression(&Position{line : 0, col : 0})
+    pub fn parse(&mut self) -> Result<Ast, Vec<ParsingError>> {
+        let parsed = self.program().map(|root| {
+            let mut ast = std::mem::take(&mut self.ast);
+            ast.root = root;
+            ast
+        });
+        let mut errors = std::mem::take(&mut self.errors);
+        match parsed {
+            Ok(ast) if errors.is_empty() => Ok(ast),
+            Ok(_) => Err(errors),
+            Err(v) => {
+                errors.extend(v);
+                Err(errors)
+            }
+        }
     }
 
-    fn expression(&mut self, last_position : &Position) -> ParsingResult {
-        self.equality(last_position)
+    /// Parses statements up to the end of the file. The last one may be an expression without a
+    /// `;`, which gives the value of the program, and a program made of such an expression
+    /// alone is that expression.
+    fn program(&mut self) -> ParsingResult {
+        let start = self.peek().from_pos;
+        let mut statements = Vec::new();
+        let mut value = None;
+        while self.peek().typ != EOF {
+            if !self.at_expression_statement() {
+                statements.push(self.statement()?);
+                continue;
+            }
+            let expr = self.expression()?;
+            if self.peek().typ == EOF {
+                if statements.is_empty() {
+                    return Ok(expr);
+                }
+                value = Some(expr);
+                break;
+            }
+            self.expect(SEMICOLON, "';'")?;
+            statements.push(expr);
+        }
+        let end = if statements.is_empty() { start } else { self.end };
+        Ok(self.ast.add(Expr::Program { statements: statements.into_boxed_slice(), value }, start, end))
     }
 
-    fn equality(&mut self, last_position : &Position) -> ParsingResult {
-        let mut expr = self.comparison(last_position)?;
-        while self.peek_match(vec![BANGEQUAL, EQUALEQUAL]) {
-            let tok = self.next().unwrap();
-            let op = match tok.typ {
-                BANGEQUAL => BinaryOp::NotEqual,
-                EQUALEQUAL => BinaryOp::Equalequal,
-                _ => unreachable!(),
-            };
-            let right = self.comparison(&tok.to_pos)?;
-            expr = PositionedExpr{
-                start : expr.start,
-                end : right.end,
-                expr : Expr::Binary {
-                    e1: Box::new(expr),
-                    op,
-                    e2: Box::new(right),
-                },
+    /// Whether the next statement is an expression followed by a `;`. At the start of a
+    /// statement, `{` opens a block rather than a map, and `fun` followed by a name declares a
+    /// function rather than starting a lambda.
+    fn at_expression_statement(&mut self) -> bool {
+        match self.peek().typ {
+            VAR | LEFTBRACE | RETURN | IF | WHILE | FOR | BREAK | CONTINUE => false,
+            FUN => !matches!(self.lex.peek_nth(1), Some(tok) if tok.typ == IDENTIFIER),
+            _ => true,
+        }
+    }
+
+    fn statement(&mut self) -> ParsingResult {
+        if self.at_expression_statement() {
+            let expr = self.expression()?;
+            self.expect(SEMICOLON, "';'")?;
+            return Ok(expr);
+        }
+        let tok = self.next();
+        match tok.typ {
+            VAR => self.var_declaration(&tok),
+            LEFTBRACE => self.block(&tok),
+            RETURN => self.return_statement(&tok),
+            FUN => self.function(&tok, true),
+            IF => self.if_statement(&tok),
+            WHILE => self.while_statement(&tok),
+            FOR => self.for_statement(&tok),
+            BREAK | CONTINUE => self.loop_jump(&tok),
+            _ => unreachable!("expression statements were parsed above"),
+        }
+    }
+
+    fn var_declaration(&mut self, var_tok : &Token) -> ParsingResult {
+        let name = self.expect(IDENTIFIER, "a variable name")?.lexeme.into();
+        let init = match self.peek().typ {
+            EQUAL => {
+                self.next();
+                // above the comma operator, like the value of an assignment
+                Some(self.expression_bp(operators::ASSIGNMENT)?)
+            }
+            _ => None,
+        };
+        let semicolon = self.expect(SEMICOLON, "'=' or ';'")?;
+        Ok(self.ast.add(Expr::Var(Box::new(Var { name, init })), var_tok.from_pos, semicolon.to_pos))
+    }
+
+    /// Parses the statements of a block up to its `}`, its `{` being `open_brace`
+    fn block(&mut self, open_brace : &Token) -> ParsingResult {
+        let mut statements = Vec::new();
+        while !matches!(self.peek().typ, RIGHTBRACE | EOF) {
+            statements.push(self.statement()?);
+        }
+        let close = self.expect(RIGHTBRACE, "'}'")?;
+        Ok(self.ast.add(Expr::Block(statements.into_boxed_slice()), open_brace.from_pos, close.to_pos))
+    }
+
+    /// Parses a return statement, which is reported outside of functions
+    fn return_statement(&mut self, return_tok : &Token) -> ParsingResult {
+        if self.functions == 0 {
+            self.errors.push(ParsingError::OutsideFunction { start: return_tok.from_pos, end: return_tok.to_pos });
+        }
+        let value = match self.peek().typ {
+            SEMICOLON => None,
+            _ => Some(self.expression()?),
+        };
+        let semicolon = self.expect(SEMICOLON, "';'")?;
+        Ok(self.ast.add(Expr::Return(value), return_tok.from_pos, semicolon.to_pos))
+    }
+
+    fn if_statement(&mut self, if_tok : &Token) -> ParsingResult {
+        let cond = self.condition()?;
+        let then = self.statement()?;
+        let otherwise = match self.peek().typ {
+            ELSE => {
+                self.next();
+                Some(self.statement()?)
+            }
+            _ => None,
+        };
+        let end = self.ast[otherwise.unwrap_or(then)].end;
+        Ok(self.ast.add(Expr::If { cond, then, otherwise }, if_tok.from_pos, end))
+    }
+
+    fn while_statement(&mut self, while_tok : &Token) -> ParsingResult {
+        let cond = self.condition()?;
+        let body = self.loop_body()?;
+        let end = self.ast[body].end;
+        Ok(self.ast.add(Expr::While { cond, body, step: None }, while_tok.from_pos, end))
+    }
+
+    /// Parses a `for` loop, which becomes a `while` loop running its step after the body, in a
+    /// block after its initializer if it has one. A missing condition is always true. A loop
+    /// over the elements of a value, `for (var x in e)`, stays a loop of its own.
+    fn for_statement(&mut self, for_tok : &Token) -> ParsingResult {
+        self.expect(LEFTPAREN, "'('")?;
+        if self.peek().typ == VAR && matches!(self.lex.peek_nth(2), Some(tok) if tok.typ == IN) {
+            return self.for_in(for_tok);
+        }
+        let init = match self.peek().typ {
+            SEMICOLON => {
+                self.next();
+                None
+            }
+            VAR => {
+                let var_tok = self.next();
+                Some(self.var_declaration(&var_tok)?)
+            }
+            _ => {
+                let init = self.expression()?;
+                self.expect(SEMICOLON, "';'")?;
+                Some(init)
+            }
+        };
+        let cond = match self.peek().typ {
+            SEMICOLON => {
+                let pos = self.peek().from_pos;
+                self.ast.add(Expr::T, pos, pos)
+            }
+            _ => self.expression()?,
+        };
+        self.expect(SEMICOLON, "';'")?;
+        let step = match self.peek().typ {
+            RIGHTPAREN => None,
+            _ => Some(self.expression()?),
+        };
+        self.expect(RIGHTPAREN, "')'")?;
+        let body = self.loop_body()?;
+        let end = self.ast[body].end;
+        let looping = self.ast.add(Expr::While { cond, body, step }, for_tok.from_pos, end);
+        Ok(match init {
+            Some(init) => self.ast.add(Expr::Block(Box::new([init, looping])), for_tok.from_pos, end),
+            None => looping,
+        })
+    }
+
+    /// Parses the rest of `for (var name in iterable) body` after its `(`
+    fn for_in(&mut self, for_tok : &Token) -> ParsingResult {
+        self.next();
+        let name = self.expect(IDENTIFIER, "a variable name")?.lexeme.into();
+        self.next();
+        let iterable = self.expression()?;
+        self.expect(RIGHTPAREN, "')'")?;
+        let body = self.loop_body()?;
+        let end = self.ast[body].end;
+        Ok(self.ast.add(Expr::ForIn(Box::new(ForIn { name, iterable, body })), for_tok.from_pos, end))
+    }
+
+    /// Parses the parenthesized condition of an `if` or a `while`
+    fn condition(&mut self) -> ParsingResult {
+        self.expect(LEFTPAREN, "'('")?;
+        let cond = self.expression()?;
+        self.expect(RIGHTPAREN, "')'")?;
+        Ok(cond)
+    }
+
+    fn loop_body(&mut self) -> ParsingResult {
+        self.loops += 1;
+        let body = self.statement();
+        self.loops -= 1;
+        body
+    }
+
+    /// Parses `break;` or `continue;`, which are reported outside of loops
+    fn loop_jump(&mut self, tok : &Token) -> ParsingResult {
+        if self.loops == 0 {
+            self.errors.push(ParsingError::OutsideLoop { start: tok.from_pos, end: tok.to_pos, keyword: tok.lexeme.to_string() });
+        }
+        let semicolon = self.expect(SEMICOLON, "';'")?;
+        let expr = match tok.typ {
+            BREAK => Expr::Break,
+            _ => Expr::Continue,
+        };
+        Ok(self.ast.add(expr, tok.from_pos, semicolon.to_pos))
+    }
+
+    /// Parses the rest of a function whose `fun` keyword is `fun_tok`, named if it is declared
+    /// by a statement and anonymous if it is a lambda.
+    fn function(&mut self, fun_tok : &Token, named : bool) -> ParsingResult {
+        let name = match named {
+            true => Some(self.expect(IDENTIFIER, "a function name")?.lexeme.into()),
+            false => None,
+        };
+        self.expect(LEFTPAREN, "'('")?;
+        let mut params = Vec::new();
+        while self.peek().typ != RIGHTPAREN {
+            params.push(self.expect(IDENTIFIER, "a parameter name")?.lexeme.into());
+            if self.peek().typ == COMMA {
+                self.next();
+            } else if self.peek().typ != RIGHTPAREN {
+                self.expect(RIGHTPAREN, "',' or ')'")?;
+            }
+        }
+        self.next();
+        let open_brace = self.expect(LEFTBRACE, "'{'")?;
+        // the loops around the function go on outside of its body
+        let loops = std::mem::replace(&mut self.loops, 0);
+        self.functions += 1;
+        let body = self.block(&open_brace);
+        self.functions -= 1;
+        self.loops = loops;
+        let body = body?;
+        let function = Function { name, params: params.into_boxed_slice(), body };
+        let end = self.ast[body].end;
+        Ok(self.ast.add(Expr::Function(Box::new(function)), fun_tok.from_pos, end))
+    }
+
+    fn expression(&mut self) -> ParsingResult {
+        self.expression_bp(0)
+    }
+
+    /// Parses an expression made of operators binding their operands at least as tightly as
+    /// `min_bp`, following the operator table.
+    fn expression_bp(&mut self, min_bp : u8) -> ParsingResult {
+        let mut expr = match operators::lookup(self.peek().typ, Fixity::Prefix) {
+            Some(op) => {
+                let tok = self.next();
+                let operand = self.expression_bp(op.bp)?;
+                let end = self.ast[operand].end;
+                self.build(op.handler, tok.from_pos, end, None, Some(operand))
+            }
+            None => match operators::lookup(self.peek().typ, Fixity::Infix) {
+                Some(op) if matches!(op.handler, Handler::Binary(_) | Handler::Comma | Handler::Assign | Handler::CompoundAssign(_)) => self.missing_left_operand(op)?,
+                _ => self.primary()?,
+            },
+        };
+
+        loop {
+            let typ = self.peek().typ;
+            if let Some(op) = operators::lookup(typ, Fixity::Postfix).filter(|op| op.bp >= min_bp) {
+                expr = self.postfix(op, expr)?;
+                continue;
+            }
+            match operators::lookup(typ, Fixity::Infix).filter(|op| op.bp >= min_bp) {
+                Some(op) => expr = self.infix(op, expr)?,
+                None => break,
             }
         }
         Ok(expr)
     }
 
-    fn comparison(&mut self, last_position : &Position) -> ParsingResult {
-        let mut expr = self.term(last_position)?;
-        while self.peek_match(vec![GREATER, GREATEREQUAL, LESS, LESSEQUAL]) {
-            let tok = self.next().unwrap();
-            let op = match tok.typ {
-                GREATER => BinaryOp::Ge,
-                GREATEREQUAL => BinaryOp::Geq,
-                LESS => BinaryOp::Le,
-                LESSEQUAL => BinaryOp::Leq,
-                _ => unreachable!(),
-            };
-            let right = self.term(&tok.to_pos)?;
-            expr = PositionedExpr{
-                start : expr.start,
-                end : right.end,
-                expr:Expr::Binary {
-                    e1: Box::new(expr),
-                    op,
-                    e2: Box::new(right),
+    /// Parses the rest of an infix operator whose left operand is `left`, starting with the
+    /// operator token itself.
+    fn infix(&mut self, op : &Operator, left : NodeId) -> ParsingResult {
+        let tok = self.next();
+        let right_bp = match op.assoc {
+            Assoc::Left => op.bp + 1,
+            Assoc::Right => op.bp,
+        };
+        if let Handler::Conditional = op.handler {
+            // anything goes between '?' and ':', as if it were parenthesized
+            let e1 = self.expression_bp(0)?;
+            let colon = self.next();
+            if colon.typ != COLON {
+                let unfinished = ParsingError::UnfinishedConditional { question_mark: tok.from_pos };
+                return Err(match colon.typ {
+                    EOF => vec![ParsingError::EarlyEof { pos: colon.from_pos, expected: "':'".to_string() }, unfinished],
+                    _ => vec![ParsingError::IllegalToken { start: colon.from_pos, end: colon.to_pos, expected: "':'".to_string() }, unfinished],
+                });
+            }
+            let e2 = self.expression_bp(right_bp)?;
+            let (start, end) = (self.ast[left].start, self.ast[e2].end);
+            return Ok(self.ast.add(Expr::Conditional { cond: left, e1, e2 }, start, end));
+        }
+        let right = self.expression_bp(right_bp)?;
+        let (start, end) = (self.ast[left].start, self.ast[right].end);
+        Ok(self.build(op.handler, start, end, Some(left), Some(right)))
+    }
+
+    /// Parses a postfix operator applied to `left`, starting with the operator token itself,
+    /// along with what follows it for indexing and calls.
+    fn postfix(&mut self, op : &Operator, left : NodeId) -> ParsingResult {
+        let tok = self.next();
+        let start = self.ast[left].start;
+        match op.handler {
+            Handler::Index => {
+                // `e1[:to]` has no index before the colon, `e1[from:]` none after it
+                let from = match self.peek().typ {
+                    COLON => None,
+                    _ => Some(self.expression()?),
+                };
+                let expr = match (from, self.peek().typ) {
+                    (_, COLON) => {
+                        self.next();
+                        let to = match self.peek().typ {
+                            RIGHTBRACKET => None,
+                            _ => Some(self.expression()?),
+                        };
+                        Expr::Slice { e1: left, from, to }
+                    }
+                    (Some(index), _) => Expr::Index { e1: left, index },
+                    (None, _) => unreachable!("an index without a colon was parsed"),
+                };
+                let close = self.expect(RIGHTBRACKET, "']'")?;
+                Ok(self.ast.add(expr, start, close.to_pos))
+            }
+            Handler::Method => {
+                let method = self.expect(IDENTIFIER, "a method name")?;
+                if self.peek().typ != LEFTPAREN {
+                    // values have methods but no fields, so a name that is not called can neither be read nor assigned to
+                    return Err(vec![ParsingError::FieldAccess { start: tok.from_pos, end: method.to_pos, name: method.lexeme.to_string() }]);
                 }
+                self.next();
+                let (args, close) = self.elements(RIGHTPAREN, "',' or ')'")?;
+                let call = MethodCall { receiver: left, method: method.lexeme.into(), args };
+                Ok(self.ast.add(Expr::MethodCall(Box::new(call)), start, close.to_pos))
             }
+            Handler::Call => {
+                let (args, close) = self.elements(RIGHTPAREN, "',' or ')'")?;
+                let call = Call { callee: left, args };
+                Ok(self.ast.add(Expr::Call(Box::new(call)), start, close.to_pos))
+            }
+            _ => Ok(self.build(op.handler, start, tok.to_pos, Some(left), None)),
         }
-        Ok(expr)
     }
 
-    fn term(&mut self, last_position : &Position) -> ParsingResult {
-        let mut expr = self.factor(last_position)?;
-        while self.peek_match(vec![MINUS, PLUS]) {
-            let tok = self.next().unwrap();
-            let op = match tok.typ {
-                MINUS => BinaryOp::BinMinus,
-                PLUS => BinaryOp::Plus,
-                _ => unreachable!(),
-            };
-            let right = self.factor(&tok.to_pos)?;
-            expr = PositionedExpr {
-                start : expr.start,
-                end : right.end,
-                expr : Expr::Binary {
-                    e1: Box::new(expr),
-                    op,
-                    e2: Box::new(right),
-                },
+    /// Parses comma-separated expressions up to the `closing` token, which is consumed and
+    /// returned. A comma may follow the last one, and each one is parsed above the comma
+    /// operator.
+    fn elements(&mut self, closing : TokenType, expected : &str) -> Result<(Box<[NodeId]>, Token<'src>), Vec<ParsingError>> {
+        let mut elements = Vec::new();
+        while self.peek().typ != closing {
+            elements.push(self.expression_bp(operators::ASSIGNMENT)?);
+            if self.peek().typ == COMMA {
+                self.next();
+            } else if self.peek().typ != closing {
+                self.expect(closing, expected)?;
             }
         }
-        Ok(expr)
+        Ok((elements.into_boxed_slice(), self.next()))
     }
 
-    fn factor(&mut self, last_position : &Position) -> ParsingResult {
-        let mut expr = self.unary(last_position)?;
-        
-        while self.peek_match(vec![SLASH, STAR]) {
-            let tok = self.next().unwrap();
-            let op = match tok.typ {
-                SLASH => BinaryOp::Div,
-                STAR => BinaryOp::Times,
-                _ => unreachable!(),
-            };
-            let right = self.unary(&tok.to_pos)?;
-            expr = PositionedExpr{
-                start : expr.start,
-                end : right.end,
-                expr : Expr::Binary {
-                    e1: Box::new(expr),
-                    op,
-                    e2: Box::new(right),
-                },
-            };   
+    /// Error production for a binary operator starting an expression, as in `* 3`. The
+    /// operator and its right operand are parsed as usual so that parsing goes on from a sane
+    /// place, and the error is recorded.
+    fn missing_left_operand(&mut self, op : &Operator) -> ParsingResult {
+        let tok = self.peek();
+        let (start, end, operator) = (tok.from_pos, tok.to_pos, tok.lexeme.to_string());
+        self.errors.push(ParsingError::MissingLeftOperand { start, end, operator });
+        let placeholder = self.ast.add(Expr::Nil, start, start);
+        self.infix(op, placeholder)
+    }
+
+    /// Builds the node for an operator from its operands, `left` being the one before the
+    /// operator and `right` the one after it.
+    fn build(&mut self, handler : Handler, start : Position, end : Position, left : Option<NodeId>, right : Option<NodeId>) -> NodeId {
+        let expr = match (handler, left, right) {
+            (Handler::Assign, Some(target), Some(value)) => return self.assignment(target, AssignOp::Set, Some(value), start, end),
+            (Handler::CompoundAssign(op), Some(target), Some(value)) => return self.assignment(target, AssignOp::Compound(op), Some(value), start, end),
+            (Handler::Step(op), Some(target), None) => return self.assignment(target, AssignOp::PostStep(op), None, start, end),
+            (Handler::Step(op), None, Some(target)) => return self.assignment(target, AssignOp::PreStep(op), None, start, end),
+            (Handler::Unary(op), Some(e1), None) | (Handler::Unary(op), None, Some(e1)) => Expr::Unary { op, e1 },
+            (Handler::Binary(op), Some(e1), Some(e2)) => Expr::Binary { e1, op, e2 },
+            (Handler::Comma, Some(e1), Some(e2)) => Expr::Comma { e1, e2 },
+            _ => unreachable!("operator table entry with the wrong fixity for its handler"),
+        };
+        self.ast.add(expr, start, end)
+    }
+
+    /// Builds an assignment spanning from `start` to `end`, after checking its target.
+    ///
+    /// Variables and elements of lists and maps are the only expressions that can be assigned
+    /// to. Other targets are reported, and stand in for the whole assignment so that parsing
+    /// goes on.
+    fn assignment(&mut self, target : NodeId, op : AssignOp, value : Option<NodeId>, start : Position, end : Position) -> NodeId {
+        if let Expr::Variable(_) | Expr::Index { .. } = self.ast[target].expr {
+            return self.ast.add(Expr::Assign { target, op, value }, start, end);
         }
-        Ok(expr)
+        let node = &mut self.ast[target];
+        // an empty target stands for a missing operand, which has already been reported
+        if node.start != node.end {
+            self.errors.push(ParsingError::InvalidAssignmentTarget { start: node.start, end: node.end });
+        }
+        node.start = start;
+        node.end = end;
+        target
     }
 
-    fn unary(&mut self, last_position : &Position) -> ParsingResult {
-        if self.peek_match(vec![BANG, MINUS]) {
-            let tok = self.next().unwrap();
-            let op = match tok.typ {
-                BANG => UnaryOp::Not,
-                MINUS => UnaryOp::UnaMinus,
+    fn primary(&mut self) -> ParsingResult {
+        let tok = self.next();
+        if tok.typ == NUMBER {
+            match Self::number_literal(&tok.lexeme, false) {
+                Some(expr) => Ok(self.ast.add(expr, tok.from_pos, tok.to_pos)),
+                None => Err(vec![ParsingError::NumberOutOfRange { start: tok.from_pos, end: tok.to_pos }]),
+            }
+        } else if [FALSE, TRUE, NIL, STRING].contains(&tok.typ) {
+            let expr = match tok.typ {
+                FALSE => Expr::F,
+                TRUE => Expr::T,
+                NIL => Expr::Nil,
+                STRING => Expr::Str(tok.lexeme[1..tok.lexeme.len() - 1].into()),
                 _ => unreachable!(),
             };
-            let right = self.unary(&tok.to_pos);
-            match right {
-                Ok(pexpr) => Ok(PositionedExpr{
-                    start : tok.from_pos,
-                    end : pexpr.end,
-                    expr: Expr::Unary { op, e1: Box::new(pexpr) },
-                }),
-                Err(v) => {
-                    Err(v)
-                },
-            }
+            Ok(self.ast.add(expr, tok.from_pos, tok.to_pos))
+        } else if tok.typ == IDENTIFIER {
+            Ok(self.ast.add(Expr::Variable(tok.lexeme.into()), tok.from_pos, tok.to_pos))
+        } else if tok.typ == LEFTPAREN {
+            self.handle_parens(&tok)
+        } else if tok.typ == LEFTBRACKET {
+            let (elements, close) = self.elements(RIGHTBRACKET, "',' or ']'")?;
+            Ok(self.ast.add(Expr::List(elements), tok.from_pos, close.to_pos))
+        } else if tok.typ == LEFTBRACE {
+            // blocks are statements, so a brace in an expression opens a map
+            self.map_literal(&tok)
+        } else if tok.typ == MATCH {
+            self.match_expr(&tok)
+        } else if tok.typ == FUN {
+            self.function(&tok, false)
+        } else if tok.typ == EOF {
+            Err(vec![ParsingError::EarlyEof { pos: tok.from_pos, expected: "an expression".to_string() }])
         } else {
-            self.primary(last_position)
+            Err(vec![ParsingError::IllegalToken { start: tok.from_pos, end: tok.to_pos, expected: "Primary token".to_string() }])
         }
     }
 
-    fn primary(&mut self, last_position : &Position) -> ParsingResult {
-        let tok = self.next();
-        match tok {
-            Some(tok) => {
-                if [FALSE, TRUE, NIL, NUMBER, STRING].contains(&tok.typ) {
-                    Ok(PositionedExpr {
-                        expr : match tok.typ {
-                            FALSE => Expr::F,
-                            TRUE => Expr::T,
-                            NIL => Expr::Nil,
-                            NUMBER => Expr::Num(str::parse(&tok.lexeme).unwrap()),
-                            STRING => Expr::Str(tok.lexeme.clone()),
-                            _ => unreachable!(),
-                        },
-                        start : tok.from_pos,
-                        end : tok.to_pos
-                    })
-                } else if tok.typ == LEFTPAREN {
-                    self.handle_parens(&tok.to_pos)
-                } else {
-                    Err(vec![ParsingError::IllegalToken { start: tok.from_pos, end: tok.to_pos, expected: "Primary token".to_string() }])
-                }
-            },
-            None => {
-                Err(vec![
-                    ParsingError::EarlyEof { pos: *last_position }
-                ])
-            },
+    /// Converts the lexeme of a NUMBER token into its value. Integers become `Expr::Num`,
+    /// anything with a fractional part or an exponent becomes `Expr::Float`.
+    /// Returns None for integers that do not fit in 64 bits. With `negative`, the value is negated,
+    /// which lets the magnitude of i64::MIN through although it does not fit as a positive number.
+    fn number_literal(lexeme : &str, negative : bool) -> Option<Expr> {
+        let digits = match lexeme.contains('_') {
+            true => Cow::Owned(lexeme.replace('_', "")),
+            false => Cow::Borrowed(lexeme),
+        };
+        let (radix, body) = match digits.get(..2) {
+            Some("0x" | "0X") => (16, &digits[2..]),
+            Some("0b" | "0B") => (2, &digits[2..]),
+            Some("0o" | "0O") => (8, &digits[2..]),
+            _ => (10, &digits[..]),
+        };
+        if radix == 10 && body.contains(['.', 'e', 'E']) {
+            body.parse().ok().map(|x : f64| Expr::Float(if negative { -x } else { x }))
+        } else {
+            let magnitude = u64::from_str_radix(body, radix).ok()?;
+            match negative {
+                true => 0i64.checked_sub_unsigned(magnitude).map(Expr::Num),
+                false => i64::try_from(magnitude).ok().map(Expr::Num),
+            }
         }
-        
     }
 
-    fn handle_parens(&mut self, last_position : &Position) -> ParsingResult {
-        let expr = self.expression(last_position);
+    fn handle_parens(&mut self, open_paren : &Token) -> ParsingResult {
+        let expr = self.expression();
         let par = self.next();
-        if par.as_ref().is_some_and(|x| x.typ == RIGHTPAREN) {
+        if par.typ == RIGHTPAREN {
             expr
         } else {
-            match (expr, par) {
-                (Ok(pexpr), None) => Err(vec![ParsingError::EarlyEof { pos: pexpr.end }, ParsingError::UnclosedParenthesis { open_paren_start: *last_position }]),
-                (Ok(pexpr), Some(_)) => Ok(pexpr),
-                (Err(mut vec), None) => {
-                    vec.push(ParsingError::EarlyEof { pos: *last_position });
+            let unclosed = ParsingError::UnclosedParenthesis { open_paren_start: open_paren.from_pos };
+            match (expr, par.typ) {
+                (Ok(_), EOF) => Err(vec![ParsingError::EarlyEof { pos: par.from_pos, expected: "')'".to_string() }, unclosed]),
+                (Ok(_), _) => Err(vec![ParsingError::IllegalToken { start: par.from_pos, end: par.to_pos, expected: "')'".to_string() }, unclosed]),
+                (Err(mut vec), EOF) => {
+                    vec.push(unclosed);
                     Err(vec)
                 }
-                (Err(vec), Some(_)) => Err(vec),
+                (Err(vec), _) => Err(vec),
+            }
+        }
+    }
+
+    /// Consumes the next token if it has the given type, and reports it otherwise.
+    fn expect(&mut self, typ : TokenType, expected : &str) -> Result<Token<'src>, Vec<ParsingError>> {
+        let tok = self.next();
+        if tok.typ == typ {
+            Ok(tok)
+        } else if tok.typ == EOF {
+            Err(vec![ParsingError::EarlyEof { pos: tok.from_pos, expected: expected.to_string() }])
+        } else {
+            Err(vec![ParsingError::IllegalToken { start: tok.from_pos, end: tok.to_pos, expected: expected.to_string() }])
+        }
+    }
+
+    /// Parses the entries of a map literal whose `{` is `open_brace`. They are separated by commas
+    /// like list elements, and so are their keys and values parsed above the comma operator.
+    fn map_literal(&mut self, open_brace : &Token) -> ParsingResult {
+        let mut entries = Vec::new();
+        while self.peek().typ != RIGHTBRACE {
+            let key = self.expression_bp(operators::ASSIGNMENT)?;
+            self.expect(COLON, "':'")?;
+            let value = self.expression_bp(operators::ASSIGNMENT)?;
+            entries.push((key, value));
+            if self.peek().typ == COMMA {
+                self.next();
+            } else if self.peek().typ != RIGHTBRACE {
+                self.expect(RIGHTBRACE, "',' or '}'")?;
+            }
+        }
+        let close = self.next();
+        Ok(self.ast.add(Expr::Map(entries.into_boxed_slice()), open_brace.from_pos, close.to_pos))
+    }
+
+    /// Parses the rest of a match expression whose `match` keyword is `match_tok`.
+    ///
+    /// Arms are separated by commas, so guards and bodies are parsed above the comma operator.
+    /// Arms after one that matches anything unconditionally are reported as unreachable.
+    fn match_expr(&mut self, match_tok : &Token) -> ParsingResult {
+        let subject = self.expression()?;
+        self.expect(LEFTBRACE, "'{'")?;
+        let mut arms = Vec::new();
+        let mut exhaustive = false;
+        while self.peek().typ != RIGHTBRACE {
+            let start = self.peek().from_pos;
+            let mut patterns = vec![self.pattern()?];
+            while self.peek().typ == PIPE {
+                self.next();
+                patterns.push(self.pattern()?);
+            }
+            let guard = match self.peek().typ {
+                IF => {
+                    self.next();
+                    Some(self.expression_bp(operators::ASSIGNMENT)?)
+                }
+                _ => None,
+            };
+            self.expect(FATARROW, "'=>'")?;
+            let body = self.expression_bp(operators::ASSIGNMENT)?;
+            let end = self.ast[body].end;
+
+            if exhaustive {
+                self.warnings.push(ParsingWarning::UnreachableArm { start, end });
+            }
+            exhaustive |= guard.is_none() && patterns.iter().any(|p| matches!(p, Pattern::Wildcard));
+            arms.push(MatchArm { patterns, guard, body, start, end });
+
+            if self.peek().typ == COMMA {
+                self.next();
+            } else if self.peek().typ != RIGHTBRACE {
+                self.expect(RIGHTBRACE, "',' or '}'")?;
+            }
+        }
+        let close = self.next();
+        Ok(self.ast.add(Expr::Match { subject, arms: arms.into_boxed_slice() }, match_tok.from_pos, close.to_pos))
+    }
+
+    /// Parses a pattern of a match arm: `_`, a literal with an optional minus sign for numbers, or
+    /// `is` followed by the name of a class
+    fn pattern(&mut self) -> Result<Pattern, Vec<ParsingError>> {
+        let tok = self.peek();
+        match tok.typ {
+            IDENTIFIER if tok.lexeme == "_" => {
+                self.next();
+                Ok(Pattern::Wildcard)
             }
+            IS => {
+                self.next();
+                let class = self.expect(IDENTIFIER, "a class name")?;
+                Ok(Pattern::Is(self.ast.add(Expr::Variable(class.lexeme.into()), class.from_pos, class.to_pos)))
+            }
+            NUMBER | STRING | TRUE | FALSE | NIL => Ok(Pattern::Literal(self.primary()?)),
+            MINUS => {
+                let minus = self.next();
+                let number = self.expect(NUMBER, "a number")?;
+                let expr = match Self::number_literal(&number.lexeme, true) {
+                    Some(expr) => expr,
+                    None => return Err(vec![ParsingError::NumberOutOfRange { start: number.from_pos, end: number.to_pos }]),
+                };
+                Ok(Pattern::Literal(self.ast.add(expr, minus.from_pos, number.to_pos)))
+            }
+            _ => {
+                let tok = self.next();
+                Err(vec![ParsingError::IllegalToken { start: tok.from_pos, end: tok.to_pos, expected: "a pattern".to_string() }])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{ParsingError, SyntaxError};
+    use crate::lexing::lexer::Lexer;
+    use crate::lexing::token::TokenType;
+    use crate::parsing::printer;
+    use crate::position::Position;
+    use super::{parse, Parser};
+
+    /// Parses the source and prints the tree as an s-expression
+    fn sexpr(src : &str) -> String {
+        printer::sexpr(&parse(src).expect(src))
+    }
+
+    /// Precedence and associativity of every level of the operator table, and how they mix
+    const CORPUS : &[(&str, &str)] = &[
+        ("1 + 2 * 3", "(+ 1 (* 2 3))"),
+        ("1 - 2 - 3", "(- (- 1 2) 3)"),
+        ("2 ** 3 ** 2", "(** 2 (** 3 2))"),
+        ("-2 ** 2", "(- (** 2 2))"),
+        ("!true == false", "(== (! true) false)"),
+        ("1 < 2 == 2 >= 1", "(== (< 1 2) (>= 2 1))"),
+        ("1 | 2 ^ 3 & 4", "(| 1 (^ 2 (& 3 4)))"),
+        ("1 << 2 + 3", "(<< 1 (+ 2 3))"),
+        ("1 ? 2 : 3 ? 4 : 5", "(?: 1 2 (?: 3 4 5))"),
+        ("1, 2 ? 3, 4 : 5", "(, 1 (?: 2 (, 3 4) 5))"),
+        ("1 ? 2 : 3, 4", "(, (?: 1 2 3) 4)"),
+        ("7 ~/ 2 % 3 / 4", "(/ (% (~/ 7 2) 3) 4)"),
+        ("~/* c */ 7 ~/ 2 // ~/", "(~/ (~ 7) 2)"),
+        ("~-1", "(~ (- 1))"),
+        ("- - 1", "(- (- 1))"),
+        ("(1 + 2) * 3", "(* (+ 1 2) 3)"),
+        ("\"a\" + \"b\" != nil", "(!= (+ \"a\" \"b\") nil)"),
+        ("-[1][0]++", "(- (post++ (index (list 1) 0)))"),
+        ("[1][0] = [2][0] = 3", "(= (index (list 1) 0) (= (index (list 2) 0) 3))"),
+        ("[1][0] += 1, 2", "(, (+= (index (list 1) 0) 1) 2)"),
+        ("x = y += 1", "(= x (+= y 1))"),
+        ("x++ + --y", "(+ (post++ x) (-- y))"),
+        ("1..2 + 3 == 4", "(.. 1 (== (+ 2 3) 4))"),
+        ("f(1)[0].len()", "(.len (index (call f 1) 0))"),
+        ("1 + 2 * 3 - 4 / 5 > 6 ? 7 : 8", "(?: (> (- (+ 1 (* 2 3)) (/ 4 5)) 6) 7 8)"),
+    ];
+
+    #[test]
+    fn operator_table_builds_expected_trees() {
+        for (src, expected) in CORPUS {
+            assert_eq!(sexpr(src), *expected, "parsing {:?}", src);
+        }
+    }
+
+    /// Negative literals of every radix in patterns, down to i64::MIN whose magnitude only fits once negated
+    #[test]
+    fn negative_patterns_keep_their_radix() {
+        assert_eq!(sexpr("match -0x10 { -0x10 => 1, _ => 2 }"), "(match (- 16) (-16 => 1) (_ => 2))");
+        assert_eq!(sexpr("match 1 { -0b11 | -0o7 | -1_000 | -1.5e1 => 1 }"), "(match 1 (-3 | -7 | -1000 | -15.0 => 1))");
+        assert_eq!(sexpr("match 1 { -9223372036854775808 | -0x8000000000000000 => 1 }"), "(match 1 (-9223372036854775808 | -9223372036854775808 => 1))");
+    }
+
+    #[test]
+    fn negative_patterns_out_of_range() {
+        for src in ["match 1 { -9223372036854775809 => 1 }", "match 1 { -0x8000000000000001 => 1 }"] {
+            let errors = parse(src).err();
+            assert!(matches!(errors.as_ref(), Some(SyntaxError::Parsing(errors)) if matches!(errors[..], [ParsingError::NumberOutOfRange { .. }])), "parsing {:?}", src);
+        }
+    }
+
+    /// Fields are reported where they are named, whether they are read or assigned to
+    #[test]
+    fn fields_are_reported_as_such() {
+        for src in ["a.b", "a.b = 1", "a.b++"] {
+            let errors = parse(src).err();
+            let (start, end) = (Position { line: 1, col: 2 }, Position { line: 1, col: 4 });
+            assert_eq!(errors, Some(SyntaxError::Parsing(vec![ParsingError::FieldAccess { start, end, name: "b".to_string() }])), "parsing {:?}", src);
         }
     }
+
+    /// `fun` followed by a name declares a function, while `fun (` starts a lambda, even at statement start
+    #[test]
+    fn lambdas_are_told_apart_from_declarations() {
+        assert_eq!(sexpr("fun f(a, b) { return a; } f"), "(program (fun f (a b) (block (return a))) => f)");
+        assert_eq!(sexpr("fun (a,) { return; }(1); 2"), "(program (call (fun (a) (block (return))) 1) => 2)");
+        assert_eq!(sexpr("var f = fun () {}; { var x; f; }"), "(program (var f (fun () (block))) (block (var x) f))");
+        assert_eq!(sexpr("1; 2"), "(program 1 => 2)");
+        assert_eq!(sexpr("1"), "1");
+    }
+
+    #[test]
+    fn return_is_only_allowed_in_functions() {
+        assert!(parse("fun f() { { return 1; } } fun () { return; }").is_ok());
+        let errors = parse("fun f() {} return 1;").err();
+        let (start, end) = (Position { line: 1, col: 12 }, Position { line: 1, col: 18 });
+        assert_eq!(errors, Some(SyntaxError::Parsing(vec![ParsingError::OutsideFunction { start, end }])));
+    }
+
+    /// `is` takes the name of a class, which is kept as a variable
+    #[test]
+    fn class_patterns_name_a_class() {
+        assert_eq!(sexpr("match x { is Point | nil => 1, _ => 2 }"), "(match x (is Point | nil => 1) (_ => 2))");
+        let errors = parse("match x { is 1 => 1 }").err();
+        let (start, end) = (Position { line: 1, col: 14 }, Position { line: 1, col: 15 });
+        assert_eq!(errors, Some(SyntaxError::Parsing(vec![ParsingError::IllegalToken { start, end, expected: "a class name".to_string() }])));
+    }
+
+    /// `break` and `continue` belong to the innermost loop of their function, and a `for` loop
+    /// becomes a `while` loop after its initializer
+    #[test]
+    fn jumps_are_only_allowed_in_loops() {
+        assert_eq!(sexpr("for (var i = 0; i < 3; i++) if (i == 1) continue; else break;"), "(program (block (var i 0) (while (< i 3) (if (== i 1) (continue) (break)) (post++ i))))");
+        assert_eq!(sexpr("for (;;) {} while (x) { fun f() {} break; }"), "(program (while true (block)) (while x (block (fun f () (block)) (break))))");
+        for (src, col, keyword) in [("break;", 1, "break"), ("while (true) fun f() { continue; }", 24, "continue"), ("if (true) break;", 11, "break")] {
+            let (start, end) = (Position { line: 1, col }, Position { line: 1, col: col + keyword.len() as u32 });
+            let errors = parse(src).err();
+            assert_eq!(errors, Some(SyntaxError::Parsing(vec![ParsingError::OutsideLoop { start, end, keyword: keyword.to_string() }])), "parsing {:?}", src);
+        }
+    }
+
+    #[test]
+    fn loops_over_values_declare_their_variable() {
+        assert_eq!(sexpr("for (var x in a, b) { break; } for (var i = 0; i < 1;) {}"), "(program (for x (, a b) (block (break))) (block (var i 0) (while (< i 1) (block))))");
+        assert_eq!(sexpr("for (var x in 0..3) for (var y in x) continue;"), "(program (for x (.. 0 3) (for y x (continue))))");
+        for src in ["for (x in l) {}", "for (var in l) {}", "for (var x in) {}", "for (var x in l {}"] {
+            assert!(parse(src).is_err(), "parsing {:?}", src);
+        }
+    }
+
+    /// Tokens that are not followed by an EOF token parse as if they were
+    #[test]
+    fn streams_without_eof_end_where_their_last_token_does() {
+        let tokens = |src| Lexer::new(src).map(|tok| tok.expect("source should lex")).filter(|tok| ![TokenType::WHITESPACE, TokenType::EOF].contains(&tok.typ));
+        let ast = Parser::new(tokens("1 + 2")).parse().expect("a whole expression should parse");
+        assert_eq!(printer::sexpr(&ast), "(+ 1 2)");
+        let errors = Parser::new(tokens("1 +")).parse().err();
+        let pos = Position { line: 1, col: 4 };
+        assert_eq!(errors, Some(vec![ParsingError::EarlyEof { pos, expected: "an expression".to_string() }]));
+        let ast = Parser::new(std::iter::empty()).parse().expect("an empty program should parse");
+        assert_eq!(printer::sexpr(&ast), "(program)");
+        assert!(Parser::new(tokens("{ 1;")).parse().is_err_and(|errors| matches!(errors[..], [ParsingError::EarlyEof { .. }])));
+    }
 }
@@ -0,0 +1,1309 @@
+use std::convert::Infallible;
+
+use crate::{
+    parsing::{
+        expr::{AssignOp, Ast, BinaryOp, Expr, Function, MatchArm, NodeId, Pattern, UnaryOp},
+        operators::{self, Assoc, ASSIGNMENT, CONDITIONAL, POSTFIX, SEQUENCE, UNARY},
+        visit::{walk_arm, walk_expr, Visitor},
+    },
+    position::Position,
+};
+
+/// Prints the expression as an indented tree, one node per line with its span.
+pub fn tree(ast: &Ast) -> String {
+    let mut printer = TreePrinter {
+        depth: 0,
+        out: String::new(),
+    };
+    let Ok(()) = printer.visit_expr(ast, ast.root);
+    printer.out
+}
+
+struct TreePrinter {
+    depth: usize,
+    out: String,
+}
+
+impl TreePrinter {
+    fn line(&mut self, node: &str, start: Position, end: Position) {
+        self.out.push_str(&format!(
+            "{}{} {}-{}\n",
+            "  ".repeat(self.depth),
+            node,
+            start,
+            end
+        ));
+    }
+}
+
+impl Visitor for TreePrinter {
+    type Error = Infallible;
+
+    fn visit_expr(&mut self, ast: &Ast, id: NodeId) -> Result<(), Infallible> {
+        let pexpr = &ast[id];
+        let node = match &pexpr.expr {
+            Expr::Num(x) => format!("Num {}", x),
+            Expr::Float(x) => format!("Float {:?}", x),
+            Expr::Str(s) => format!("Str {:?}", s),
+            Expr::T => "True".to_string(),
+            Expr::F => "False".to_string(),
+            Expr::Nil => "Nil".to_string(),
+            Expr::Variable(name) => format!("Variable {}", name),
+            Expr::Unary { op, .. } => format!("Unary {}", op),
+            Expr::Binary { op, .. } => format!("Binary {}", op),
+            Expr::Conditional { .. } => "Conditional".to_string(),
+            Expr::Comma { .. } => "Comma".to_string(),
+            Expr::Match { .. } => "Match".to_string(),
+            Expr::List(_) => "List".to_string(),
+            Expr::Map(_) => "Map".to_string(),
+            Expr::Index { .. } => "Index".to_string(),
+            Expr::Slice { from, to, .. } => format!(
+                "Slice {}:{}",
+                if from.is_some() { "from" } else { "" },
+                if to.is_some() { "to" } else { "" }
+            ),
+            Expr::MethodCall(call) => format!("MethodCall {}", call.method),
+            Expr::Call(_) => "Call".to_string(),
+            Expr::Assign { op, .. } => match op {
+                AssignOp::PreStep(_) => format!("Assign prefix {}", op),
+                AssignOp::PostStep(_) => format!("Assign postfix {}", op),
+                _ => format!("Assign {}", op),
+            },
+            Expr::Function(function) => format!("Function {}", signature(function)),
+            Expr::Program { .. } => "Program".to_string(),
+            Expr::Block(_) => "Block".to_string(),
+            Expr::Var(var) => format!("Var {}", var.name),
+            Expr::Return(_) => "Return".to_string(),
+            Expr::If { otherwise, .. } => match otherwise {
+                Some(_) => "If else".to_string(),
+                None => "If".to_string(),
+            },
+            Expr::While { step, .. } => match step {
+                Some(_) => "While with step".to_string(),
+                None => "While".to_string(),
+            },
+            Expr::ForIn(for_in) => format!("ForIn {}", for_in.name),
+            Expr::Break => "Break".to_string(),
+            Expr::Continue => "Continue".to_string(),
+        };
+        self.line(&node, pexpr.start, pexpr.end);
+        self.depth += 1;
+        walk_expr(self, ast, id)?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn visit_arm(&mut self, ast: &Ast, arm: &MatchArm) -> Result<(), Infallible> {
+        let guard = if arm.guard.is_some() { " if" } else { "" };
+        self.line(
+            &format!("Arm {}{}", patterns(ast, arm), guard),
+            arm.start,
+            arm.end,
+        );
+        self.depth += 1;
+        walk_arm(self, ast, arm)?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    /// Patterns are printed on the line of their arm
+    fn visit_pattern(&mut self, _ast: &Ast, _pattern: &Pattern) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Prints the expression as a Lisp-style S-expression, such as `(* (- 1) (+ 2 3))`.
+pub fn sexpr(ast: &Ast) -> String {
+    sexpr_node(ast, ast.root)
+}
+
+fn sexpr_node(ast: &Ast, id: NodeId) -> String {
+    let sexpr = |id: &NodeId| sexpr_node(ast, *id);
+    match &ast[id].expr {
+        Expr::Num(x) => x.to_string(),
+        Expr::Float(x) => format!("{:?}", x),
+        Expr::Str(s) => format!("{:?}", s),
+        Expr::T => "true".to_string(),
+        Expr::F => "false".to_string(),
+        Expr::Nil => "nil".to_string(),
+        Expr::Variable(name) => name.to_string(),
+        Expr::Unary { op, e1 } => format!("({} {})", op, sexpr(e1)),
+        Expr::Binary { e1, op, e2 } => format!("({} {} {})", op, sexpr(e1), sexpr(e2)),
+        Expr::Conditional { cond, e1, e2 } => {
+            format!("(?: {} {} {})", sexpr(cond), sexpr(e1), sexpr(e2))
+        }
+        Expr::Comma { e1, e2 } => format!("(, {} {})", sexpr(e1), sexpr(e2)),
+        Expr::Match { subject, arms } => {
+            let arms: Vec<String> = arms
+                .iter()
+                .map(|arm| match &arm.guard {
+                    Some(guard) => format!(
+                        "({} if {} => {})",
+                        patterns(ast, arm),
+                        sexpr(guard),
+                        sexpr(&arm.body)
+                    ),
+                    None => format!("({} => {})", patterns(ast, arm), sexpr(&arm.body)),
+                })
+                .collect();
+            format!("(match {} {})", sexpr(subject), arms.join(" "))
+        }
+        Expr::List(elements) => {
+            let elements: String = elements
+                .iter()
+                .map(|element| format!(" {}", sexpr(element)))
+                .collect();
+            format!("(list{})", elements)
+        }
+        Expr::Map(entries) => {
+            let entries: String = entries
+                .iter()
+                .map(|(key, value)| format!(" ({} {})", sexpr(key), sexpr(value)))
+                .collect();
+            format!("(map{})", entries)
+        }
+        Expr::Index { e1, index } => format!("(index {} {})", sexpr(e1), sexpr(index)),
+        Expr::Slice { e1, from, to } => {
+            let bound = |bound: &Option<NodeId>| bound.as_ref().map_or("_".to_string(), sexpr);
+            format!("(slice {} {} {})", sexpr(e1), bound(from), bound(to))
+        }
+        Expr::MethodCall(call) => {
+            let args: String = call
+                .args
+                .iter()
+                .map(|arg| format!(" {}", sexpr(arg)))
+                .collect();
+            format!("(.{} {}{})", call.method, sexpr(&call.receiver), args)
+        }
+        Expr::Call(call) => {
+            let args: String = call
+                .args
+                .iter()
+                .map(|arg| format!(" {}", sexpr(arg)))
+                .collect();
+            format!("(call {}{})", sexpr(&call.callee), args)
+        }
+        Expr::Assign { target, op, value } => match (op, value) {
+            (AssignOp::PostStep(_), _) => format!("(post{} {})", op, sexpr(target)),
+            (_, Some(value)) => format!("({} {} {})", op, sexpr(target), sexpr(value)),
+            (_, None) => format!("({} {})", op, sexpr(target)),
+        },
+        Expr::Function(function) => {
+            let name: String = function
+                .name
+                .iter()
+                .map(|name| format!(" {}", name))
+                .collect();
+            format!(
+                "(fun{} ({}) {})",
+                name,
+                function.params.join(" "),
+                sexpr(&function.body)
+            )
+        }
+        Expr::Program { statements, value } => {
+            let statements: String = statements
+                .iter()
+                .map(|statement| format!(" {}", sexpr(statement)))
+                .collect();
+            let value: String = value
+                .iter()
+                .map(|value| format!(" => {}", sexpr(value)))
+                .collect();
+            format!("(program{}{})", statements, value)
+        }
+        Expr::Block(statements) => {
+            let statements: String = statements
+                .iter()
+                .map(|statement| format!(" {}", sexpr(statement)))
+                .collect();
+            format!("(block{})", statements)
+        }
+        Expr::Var(var) => match &var.init {
+            Some(init) => format!("(var {} {})", var.name, sexpr(init)),
+            None => format!("(var {})", var.name),
+        },
+        Expr::Return(value) => match value {
+            Some(value) => format!("(return {})", sexpr(value)),
+            None => "(return)".to_string(),
+        },
+        Expr::If {
+            cond,
+            then,
+            otherwise,
+        } => match otherwise {
+            Some(otherwise) => format!("(if {} {} {})", sexpr(cond), sexpr(then), sexpr(otherwise)),
+            None => format!("(if {} {})", sexpr(cond), sexpr(then)),
+        },
+        Expr::While { cond, body, step } => match step {
+            Some(step) => format!("(while {} {} {})", sexpr(cond), sexpr(body), sexpr(step)),
+            None => format!("(while {} {})", sexpr(cond), sexpr(body)),
+        },
+        Expr::ForIn(for_in) => format!(
+            "(for {} {} {})",
+            for_in.name,
+            sexpr(&for_in.iterable),
+            sexpr(&for_in.body)
+        ),
+        Expr::Break => "(break)".to_string(),
+        Expr::Continue => "(continue)".to_string(),
+    }
+}
+
+/// The name of a function, if it has one, and its parameters, as written in the source
+fn signature(function: &Function) -> String {
+    let name = function.name.as_deref().unwrap_or("");
+    format!("{}({})", name, function.params.join(", "))
+}
+
+/// The alternative patterns of an arm, as written in the source
+fn patterns(ast: &Ast, arm: &MatchArm) -> String {
+    let patterns: Vec<String> = arm
+        .patterns
+        .iter()
+        .map(|pattern| match pattern {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Literal(literal) => sexpr_node(ast, *literal),
+            Pattern::Is(class) => format!("is {}", sexpr_node(ast, *class)),
+        })
+        .collect();
+    patterns.join(" | ")
+}
+
+/// Prints the expression as Lox source, with parentheses only where precedence and
+/// associativity require them, so that parsing the output gives back the same tree. Strings
+/// holding a `"` cannot be written in Lox, and are printed as they are. An `if` without `else`
+/// ending the first branch of an `if` with one is put in a block, as the `else` would go to it.
+pub fn source(ast: &Ast) -> String {
+    match &ast[ast.root].expr {
+        Expr::Program { statements, value } => {
+            let mut statements: Vec<String> = statements
+                .iter()
+                .map(|statement| statement_source(ast, *statement))
+                .collect();
+            statements.extend(value.map(|value| at_statement_start(source_node(ast, value).text)));
+            statements.join(" ")
+        }
+        _ => at_statement_start(source_node(ast, ast.root).text),
+    }
+}
+
+/// Source of a statement, on a single line
+fn statement_source(ast: &Ast, id: NodeId) -> String {
+    match &ast[id].expr {
+        Expr::Var(var) => match &var.init {
+            Some(init) => {
+                let init = source_node(ast, *init).wrap(ASSIGNMENT, None);
+                format!("var {} = {};", var.name, init.text)
+            }
+            None => format!("var {};", var.name),
+        },
+        Expr::Block(statements) if for_loop(ast, statements).is_some() => {
+            let (init, looping) = for_loop(ast, statements).expect("the block is a `for` loop");
+            let init = statement_source(ast, init);
+            let Expr::While {
+                cond,
+                body,
+                step: Some(step),
+            } = &ast[looping].expr
+            else {
+                unreachable!("a `for` loop has a step")
+            };
+            format!(
+                "for ({} {}; {}) {}",
+                init,
+                source_node(ast, *cond).text,
+                source_node(ast, *step).text,
+                statement_source(ast, *body)
+            )
+        }
+        Expr::Block(statements) => block_source(ast, statements),
+        Expr::Return(Some(value)) => format!("return {};", source_node(ast, *value).text),
+        Expr::Return(None) => "return;".to_string(),
+        Expr::Function(function) if function.name.is_some() => {
+            format!("fun {} {}", signature(function), body_source(ast, function))
+        }
+        Expr::If {
+            cond,
+            then,
+            otherwise,
+        } => {
+            let cond = source_node(ast, *cond).text;
+            let then = match otherwise.is_some() && ends_with_lone_if(ast, *then) {
+                true => format!("{{ {} }}", statement_source(ast, *then)),
+                false => statement_source(ast, *then),
+            };
+            match otherwise {
+                Some(otherwise) => format!(
+                    "if ({}) {} else {}",
+                    cond,
+                    then,
+                    statement_source(ast, *otherwise)
+                ),
+                None => format!("if ({}) {}", cond, then),
+            }
+        }
+        Expr::While { cond, body, step } => {
+            let cond = source_node(ast, *cond).text;
+            let body = statement_source(ast, *body);
+            match step {
+                Some(step) => format!(
+                    "for (; {}; {}) {}",
+                    cond,
+                    source_node(ast, *step).text,
+                    body
+                ),
+                None => format!("while ({}) {}", cond, body),
+            }
+        }
+        Expr::ForIn(for_in) => format!(
+            "for (var {} in {}) {}",
+            for_in.name,
+            source_node(ast, for_in.iterable).text,
+            statement_source(ast, for_in.body)
+        ),
+        Expr::Break => "break;".to_string(),
+        Expr::Continue => "continue;".to_string(),
+        _ => format!("{};", at_statement_start(source_node(ast, id).text)),
+    }
+}
+
+/// Source of a block, in braces
+fn block_source(ast: &Ast, statements: &[NodeId]) -> String {
+    if statements.is_empty() {
+        return "{}".to_string();
+    }
+    let statements: Vec<String> = statements
+        .iter()
+        .map(|statement| statement_source(ast, *statement))
+        .collect();
+    format!("{{ {} }}", statements.join(" "))
+}
+
+/// Source of the body of a function, in braces even if it would print as a `for` loop
+fn body_source(ast: &Ast, function: &Function) -> String {
+    match &ast[function.body].expr {
+        Expr::Block(statements) => block_source(ast, statements),
+        _ => unreachable!("the body of a function is a block"),
+    }
+}
+
+/// The declaration and the loop of a block that is a `for` loop declaring a variable, which
+/// prints as such
+fn for_loop(ast: &Ast, statements: &[NodeId]) -> Option<(NodeId, NodeId)> {
+    match *statements {
+        [init, looping] => match (&ast[init].expr, &ast[looping].expr) {
+            (Expr::Var(_), Expr::While { step: Some(_), .. }) => Some((init, looping)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether the source of the statement ends with an `if` without `else`, which would take an
+/// `else` written after it
+fn ends_with_lone_if(ast: &Ast, id: NodeId) -> bool {
+    match &ast[id].expr {
+        Expr::If {
+            otherwise: None, ..
+        } => true,
+        Expr::If {
+            otherwise: Some(otherwise),
+            ..
+        } => ends_with_lone_if(ast, *otherwise),
+        Expr::While { body, .. } => ends_with_lone_if(ast, *body),
+        Expr::ForIn(for_in) => ends_with_lone_if(ast, for_in.body),
+        Expr::Block(statements) => match for_loop(ast, statements) {
+            Some((_, looping)) => ends_with_lone_if(ast, looping),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// The source of an expression, parenthesized if it starts with a map, whose `{` would open a
+/// block at the start of a statement
+fn at_statement_start(text: String) -> String {
+    match text.starts_with('{') {
+        true => format!("({})", text),
+        false => text,
+    }
+}
+
+/// Binding power of literals and parenthesized expressions, which no operator can split
+const ATOM: u8 = u8::MAX;
+
+/// Source of an expression, along with how it binds to the operators next to it
+struct Source {
+    text: String,
+    /// Binding power of its loosest operator outside parentheses. It needs parentheses where
+    /// the parser expects operators binding at least as tightly.
+    bp: u8,
+    /// Lowest binding power an operator following it needs to take its last operand away
+    right_bp: u8,
+}
+
+impl Source {
+    fn atom(text: String) -> Source {
+        Source {
+            text,
+            bp: ATOM,
+            right_bp: ATOM,
+        }
+    }
+
+    /// The text, parenthesized if it would not parse back on its own where the parser expects
+    /// operators binding at least `min_bp`, followed by an operator binding `next_bp`.
+    fn wrap(self, min_bp: u8, next_bp: Option<u8>) -> Source {
+        if self.bp < min_bp || next_bp.is_some_and(|next_bp| next_bp >= self.right_bp) {
+            Source::atom(format!("({})", self.text))
+        } else {
+            self
+        }
+    }
+}
+
+fn source_node(ast: &Ast, id: NodeId) -> Source {
+    let source = |id: &NodeId| source_node(ast, *id);
+    match &ast[id].expr {
+        Expr::Num(x) => number(*x),
+        Expr::Float(x) => float(*x),
+        Expr::Str(s) => Source::atom(format!("\"{}\"", s)),
+        Expr::T => Source::atom("true".to_string()),
+        Expr::F => Source::atom("false".to_string()),
+        Expr::Nil => Source::atom("nil".to_string()),
+        Expr::Variable(name) => Source::atom(name.to_string()),
+        Expr::Unary { op, e1 } => {
+            let operand = source(e1).wrap(UNARY, None);
+            // `- -x` must not be glued into `--x`
+            let space = *op == UnaryOp::UnaMinus && operand.text.starts_with('-');
+            Source {
+                text: format!("{}{}{}", op, if space { " " } else { "" }, operand.text),
+                bp: ATOM,
+                right_bp: operand.right_bp.min(UNARY),
+            }
+        }
+        Expr::Binary { e1, op, e2 } => {
+            let operator = operators::binary(*op);
+            let right_bp = match operator.assoc {
+                Assoc::Left => operator.bp + 1,
+                Assoc::Right => operator.bp,
+            };
+            let left = source(e1).wrap(0, Some(operator.bp));
+            let right = source(e2).wrap(right_bp, None);
+            // ranges are written without spaces, like `0..10`
+            let space = match op {
+                BinaryOp::Range | BinaryOp::RangeInclusive => "",
+                _ => " ",
+            };
+            Source {
+                text: format!("{}{}{}{}{}", left.text, space, op, space, right.text),
+                bp: operator.bp,
+                right_bp: right.right_bp.min(right_bp),
+            }
+        }
+        Expr::Conditional { cond, e1, e2 } => {
+            let cond = source(cond).wrap(0, Some(CONDITIONAL));
+            // the middle branch is delimited by `?` and `:`
+            let e1 = source(e1);
+            let e2 = source(e2).wrap(CONDITIONAL, None);
+            Source {
+                text: format!("{} ? {} : {}", cond.text, e1.text, e2.text),
+                bp: CONDITIONAL,
+                right_bp: e2.right_bp.min(CONDITIONAL),
+            }
+        }
+        Expr::Comma { e1, e2 } => {
+            let e1 = source(e1).wrap(0, Some(SEQUENCE));
+            let e2 = source(e2).wrap(SEQUENCE + 1, None);
+            Source {
+                text: format!("{}, {}", e1.text, e2.text),
+                bp: SEQUENCE,
+                right_bp: e2.right_bp.min(SEQUENCE + 1),
+            }
+        }
+        Expr::Match { subject, arms } => {
+            let arms: Vec<String> = arms
+                .iter()
+                .map(|arm| {
+                    // guards and bodies are parsed above the comma separating the arms
+                    let above_comma = |id: &NodeId| source(id).wrap(operators::ASSIGNMENT, None);
+                    let patterns: Vec<String> = arm
+                        .patterns
+                        .iter()
+                        .map(|pattern| match pattern {
+                            Pattern::Wildcard => "_".to_string(),
+                            Pattern::Literal(literal) => pattern_literal(ast, *literal),
+                            Pattern::Is(class) => format!("is {}", source(class).text),
+                        })
+                        .collect();
+                    match &arm.guard {
+                        Some(guard) => format!(
+                            "{} if {} => {}",
+                            patterns.join(" | "),
+                            above_comma(guard).text,
+                            above_comma(&arm.body).text
+                        ),
+                        None => format!(
+                            "{} => {}",
+                            patterns.join(" | "),
+                            above_comma(&arm.body).text
+                        ),
+                    }
+                })
+                .collect();
+            let subject = source(subject).text;
+            if arms.is_empty() {
+                Source::atom(format!("match {} {{}}", subject))
+            } else {
+                Source::atom(format!("match {} {{ {} }}", subject, arms.join(", ")))
+            }
+        }
+        // elements and arguments are parsed above the comma separating them
+        Expr::List(elements) => {
+            let elements: Vec<String> = elements
+                .iter()
+                .map(|element| source(element).wrap(ASSIGNMENT, None).text)
+                .collect();
+            Source::atom(format!("[{}]", elements.join(", ")))
+        }
+        Expr::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| {
+                    let key = source(key).wrap(ASSIGNMENT, None);
+                    let value = source(value).wrap(ASSIGNMENT, None);
+                    format!("{}: {}", key.text, value.text)
+                })
+                .collect();
+            Source::atom(format!("{{{}}}", entries.join(", ")))
+        }
+        Expr::Index { e1, index } => {
+            let e1 = source(e1).wrap(0, Some(POSTFIX));
+            Source::atom(format!("{}[{}]", e1.text, source(index).text))
+        }
+        Expr::Slice { e1, from, to } => {
+            let e1 = source(e1).wrap(0, Some(POSTFIX));
+            let bound =
+                |bound: &Option<NodeId>| bound.as_ref().map_or(String::new(), |b| source(b).text);
+            Source::atom(format!("{}[{}:{}]", e1.text, bound(from), bound(to)))
+        }
+        Expr::MethodCall(call) => {
+            let receiver = source(&call.receiver).wrap(0, Some(POSTFIX));
+            let args: Vec<String> = call
+                .args
+                .iter()
+                .map(|arg| source(arg).wrap(ASSIGNMENT, None).text)
+                .collect();
+            Source::atom(format!(
+                "{}.{}({})",
+                receiver.text,
+                call.method,
+                args.join(", ")
+            ))
+        }
+        Expr::Call(call) => {
+            let callee = source(&call.callee).wrap(0, Some(POSTFIX));
+            let args: Vec<String> = call
+                .args
+                .iter()
+                .map(|arg| source(arg).wrap(ASSIGNMENT, None).text)
+                .collect();
+            Source::atom(format!("{}({})", callee.text, args.join(", ")))
+        }
+        Expr::Assign { target, op, value } => {
+            let target = source(target).wrap(0, Some(POSTFIX));
+            match (op, value) {
+                (AssignOp::PreStep(_), _) => Source {
+                    text: format!("{}{}", op, target.text),
+                    bp: ATOM,
+                    right_bp: UNARY,
+                },
+                (AssignOp::PostStep(_), _) => Source::atom(format!("{}{}", target.text, op)),
+                (_, Some(value)) => {
+                    let value = source(value).wrap(ASSIGNMENT, None);
+                    Source {
+                        text: format!("{} {} {}", target.text, op, value.text),
+                        bp: ASSIGNMENT,
+                        right_bp: value.right_bp.min(ASSIGNMENT),
+                    }
+                }
+                (_, None) => unreachable!("only steps have no value"),
+            }
+        }
+        Expr::Function(function) => Source::atom(format!(
+            "fun {} {}",
+            signature(function),
+            body_source(ast, function)
+        )),
+        Expr::Program { .. }
+        | Expr::Block(_)
+        | Expr::Var(_)
+        | Expr::Return(_)
+        | Expr::If { .. }
+        | Expr::While { .. }
+        | Expr::ForIn(_)
+        | Expr::Break
+        | Expr::Continue => Source::atom(statement_source(ast, id)),
+    }
+}
+
+/// Literal of a pattern, where a minus sign is part of the number, so that negative numbers
+/// are written as they are, i64::MIN included
+fn pattern_literal(ast: &Ast, id: NodeId) -> String {
+    match ast[id].expr {
+        Expr::Num(x) => x.to_string(),
+        _ => source_node(ast, id).text,
+    }
+}
+
+/// Negative numbers, which can come from a rewriting of the tree, are written as a negation.
+/// Patterns print theirs with `pattern_literal`, as they parse back as literals there.
+fn number(x: i64) -> Source {
+    match x {
+        // its absolute value does not fit in a literal
+        i64::MIN => Source::atom(format!("({} - 1)", i64::MIN + 1)),
+        x if x < 0 => Source {
+            text: x.to_string(),
+            bp: ATOM,
+            right_bp: UNARY,
+        },
+        x => Source::atom(x.to_string()),
+    }
+}
+
+fn float(x: f64) -> Source {
+    if x.is_nan() {
+        // there is no literal for it, but infinities make one
+        return Source::atom("(1e999 - 1e999)".to_string());
+    }
+    let text = if x.is_infinite() {
+        "1e999".to_string()
+    } else {
+        format!("{:?}", x.abs())
+    };
+    if x.is_sign_negative() {
+        Source {
+            text: format!("-{}", text),
+            bp: ATOM,
+            right_bp: UNARY,
+        }
+    } else {
+        Source::atom(text)
+    }
+}
+
+/// Prints the expression as a JSON object. Every node has a `type`, a `start` and an `end`,
+/// along with its value or operator and children.
+pub fn json(ast: &Ast) -> String {
+    json_node(ast, ast.root)
+}
+
+fn json_node(ast: &Ast, id: NodeId) -> String {
+    let json = |id: &NodeId| json_node(ast, *id);
+    let pexpr = &ast[id];
+    let fields = match &pexpr.expr {
+        Expr::Num(x) => format!("\"type\":\"Num\",\"value\":{}", x),
+        Expr::Float(x) if x.is_finite() => format!("\"type\":\"Float\",\"value\":{:?}", x),
+        Expr::Float(_) => "\"type\":\"Float\",\"value\":null".to_string(),
+        Expr::Str(s) => format!("\"type\":\"Str\",\"value\":{}", json_string(s)),
+        Expr::T => "\"type\":\"Bool\",\"value\":true".to_string(),
+        Expr::F => "\"type\":\"Bool\",\"value\":false".to_string(),
+        Expr::Nil => "\"type\":\"Nil\"".to_string(),
+        Expr::Variable(name) => format!("\"type\":\"Variable\",\"name\":{}", json_string(name)),
+        Expr::Unary { op, e1 } => format!(
+            "\"type\":\"Unary\",\"op\":\"{}\",\"operand\":{}",
+            op,
+            json(e1)
+        ),
+        Expr::Binary { e1, op, e2 } => format!(
+            "\"type\":\"Binary\",\"op\":\"{}\",\"left\":{},\"right\":{}",
+            op,
+            json(e1),
+            json(e2)
+        ),
+        Expr::Conditional { cond, e1, e2 } => format!(
+            "\"type\":\"Conditional\",\"cond\":{},\"then\":{},\"else\":{}",
+            json(cond),
+            json(e1),
+            json(e2)
+        ),
+        Expr::Comma { e1, e2 } => format!(
+            "\"type\":\"Comma\",\"left\":{},\"right\":{}",
+            json(e1),
+            json(e2)
+        ),
+        Expr::Match { subject, arms } => {
+            let arms: Vec<String> = arms.iter().map(|arm| json_arm(ast, arm)).collect();
+            format!(
+                "\"type\":\"Match\",\"subject\":{},\"arms\":[{}]",
+                json(subject),
+                arms.join(",")
+            )
+        }
+        Expr::List(elements) => {
+            let elements: Vec<String> = elements.iter().map(json).collect();
+            format!("\"type\":\"List\",\"elements\":[{}]", elements.join(","))
+        }
+        Expr::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| format!("{{\"key\":{},\"value\":{}}}", json(key), json(value)))
+                .collect();
+            format!("\"type\":\"Map\",\"entries\":[{}]", entries.join(","))
+        }
+        Expr::Index { e1, index } => format!(
+            "\"type\":\"Index\",\"indexed\":{},\"index\":{}",
+            json(e1),
+            json(index)
+        ),
+        Expr::Slice { e1, from, to } => {
+            let bound = |bound: &Option<NodeId>| bound.as_ref().map_or("null".to_string(), json);
+            format!(
+                "\"type\":\"Slice\",\"sliced\":{},\"from\":{},\"to\":{}",
+                json(e1),
+                bound(from),
+                bound(to)
+            )
+        }
+        Expr::MethodCall(call) => {
+            let args: Vec<String> = call.args.iter().map(json).collect();
+            format!(
+                "\"type\":\"MethodCall\",\"receiver\":{},\"method\":{},\"args\":[{}]",
+                json(&call.receiver),
+                json_string(&call.method),
+                args.join(",")
+            )
+        }
+        Expr::Call(call) => {
+            let args: Vec<String> = call.args.iter().map(json).collect();
+            format!(
+                "\"type\":\"Call\",\"callee\":{},\"args\":[{}]",
+                json(&call.callee),
+                args.join(",")
+            )
+        }
+        Expr::Assign { target, op, value } => {
+            let fixity = match op {
+                AssignOp::PreStep(_) => ",\"fixity\":\"prefix\"",
+                AssignOp::PostStep(_) => ",\"fixity\":\"postfix\"",
+                _ => "",
+            };
+            format!(
+                "\"type\":\"Assign\",\"op\":\"{}\"{},\"target\":{},\"value\":{}",
+                op,
+                fixity,
+                json(target),
+                value.as_ref().map_or("null".to_string(), json)
+            )
+        }
+        Expr::Function(function) => {
+            let params: Vec<String> = function.params.iter().map(|p| json_string(p)).collect();
+            format!(
+                "\"type\":\"Function\",\"name\":{},\"params\":[{}],\"body\":{}",
+                function
+                    .name
+                    .as_deref()
+                    .map_or("null".to_string(), json_string),
+                params.join(","),
+                json(&function.body)
+            )
+        }
+        Expr::Program { statements, value } => {
+            let statements: Vec<String> = statements.iter().map(json).collect();
+            format!(
+                "\"type\":\"Program\",\"statements\":[{}],\"value\":{}",
+                statements.join(","),
+                value.as_ref().map_or("null".to_string(), json)
+            )
+        }
+        Expr::Block(statements) => {
+            let statements: Vec<String> = statements.iter().map(json).collect();
+            format!(
+                "\"type\":\"Block\",\"statements\":[{}]",
+                statements.join(",")
+            )
+        }
+        Expr::Var(var) => format!(
+            "\"type\":\"Var\",\"name\":{},\"init\":{}",
+            json_string(&var.name),
+            var.init.as_ref().map_or("null".to_string(), json)
+        ),
+        Expr::Return(value) => format!(
+            "\"type\":\"Return\",\"value\":{}",
+            value.as_ref().map_or("null".to_string(), json)
+        ),
+        Expr::If {
+            cond,
+            then,
+            otherwise,
+        } => format!(
+            "\"type\":\"If\",\"cond\":{},\"then\":{},\"else\":{}",
+            json(cond),
+            json(then),
+            otherwise.as_ref().map_or("null".to_string(), json)
+        ),
+        Expr::While { cond, body, step } => format!(
+            "\"type\":\"While\",\"cond\":{},\"body\":{},\"step\":{}",
+            json(cond),
+            json(body),
+            step.as_ref().map_or("null".to_string(), json)
+        ),
+        Expr::ForIn(for_in) => format!(
+            "\"type\":\"ForIn\",\"name\":{},\"iterable\":{},\"body\":{}",
+            json_string(&for_in.name),
+            json(&for_in.iterable),
+            json(&for_in.body)
+        ),
+        Expr::Break => "\"type\":\"Break\"".to_string(),
+        Expr::Continue => "\"type\":\"Continue\"".to_string(),
+    };
+    format!(
+        "{{{},\"start\":{{\"line\":{},\"col\":{}}},\"end\":{{\"line\":{},\"col\":{}}}}}",
+        fields, pexpr.start.line, pexpr.start.col, pexpr.end.line, pexpr.end.col
+    )
+}
+
+fn json_arm(ast: &Ast, arm: &MatchArm) -> String {
+    let patterns: Vec<String> = arm
+        .patterns
+        .iter()
+        .map(|pattern| match pattern {
+            Pattern::Wildcard => "{\"type\":\"Wildcard\"}".to_string(),
+            Pattern::Literal(literal) => json_node(ast, *literal),
+            Pattern::Is(class) => {
+                format!("{{\"type\":\"Is\",\"class\":{}}}", json_node(ast, *class))
+            }
+        })
+        .collect();
+    format!(
+        "{{\"patterns\":[{}],\"guard\":{},\"body\":{}}}",
+        patterns.join(","),
+        arm.guard
+            .map_or("null".to_string(), |guard| json_node(ast, guard)),
+        json_node(ast, arm.body)
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+    use crate::parsing::{
+        expr::{Call, ForIn, Function, MethodCall, Var},
+        parser::parse,
+        visit::{walk_expr_mut, VisitorMut},
+    };
+
+    #[test]
+    fn negative_patterns_print_as_literals() {
+        let src = "match 1 { -9223372036854775808 | -0x10 | -1e999 | -2.5 => 1, _ => 2 }";
+        let printed = source(&parse(src).expect(src));
+        assert_eq!(
+            printed,
+            "match 1 { -9223372036854775808 | -16 | -1e999 | -2.5 => 1, _ => 2 }"
+        );
+        assert_eq!(
+            sexpr(&parse(&printed).expect(&printed)),
+            sexpr(&parse(src).expect(src))
+        );
+    }
+
+    /// Xorshift generator, so that a failing tree can be built again from the seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len() as u64) as usize]
+        }
+    }
+
+    const UNARY_OPS: [UnaryOp; 3] = [UnaryOp::UnaMinus, UnaryOp::Not, UnaryOp::BitNot];
+
+    const BINARY_OPS: [BinaryOp; 20] = [
+        BinaryOp::Equalequal,
+        BinaryOp::NotEqual,
+        BinaryOp::Le,
+        BinaryOp::Leq,
+        BinaryOp::Ge,
+        BinaryOp::Geq,
+        BinaryOp::Plus,
+        BinaryOp::BinMinus,
+        BinaryOp::Times,
+        BinaryOp::Div,
+        BinaryOp::IntDiv,
+        BinaryOp::Mod,
+        BinaryOp::Pow,
+        BinaryOp::BitAnd,
+        BinaryOp::BitOr,
+        BinaryOp::BitXor,
+        BinaryOp::Shl,
+        BinaryOp::Shr,
+        BinaryOp::Range,
+        BinaryOp::RangeInclusive,
+    ];
+
+    const ASSIGN_OPS: [AssignOp; 4] = [
+        AssignOp::Set,
+        AssignOp::Compound(BinaryOp::Times),
+        AssignOp::PreStep(BinaryOp::BinMinus),
+        AssignOp::PostStep(BinaryOp::Plus),
+    ];
+
+    /// A literal, or a number that no literal spells, as rewriting a tree can leave: negative
+    /// numbers, i64::MIN, infinities and NaN. Patterns never hold NaN, as they only come from the
+    /// parser and have no syntax for it.
+    fn literal(rng: &mut Rng, ast: &mut Ast, pattern: bool) -> NodeId {
+        let expr = match rng.below(9) {
+            0 => Expr::Num(rng.next() as i64),
+            1 => Expr::Float(f64::from_bits(rng.next())),
+            2 => Expr::Str("a b".into()),
+            3 => Expr::T,
+            4 => Expr::F,
+            5 => Expr::Nil,
+            6 => Expr::Num(rng.pick(&[i64::MIN, i64::MIN + 1, -1, 0, i64::MAX])),
+            7 => Expr::Float(rng.pick(&[f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0])),
+            _ => Expr::Num(rng.below(10) as i64 - 5),
+        };
+        let expr = match expr {
+            Expr::Float(x) if x.is_nan() && pattern => Expr::Float(-1.5),
+            expr => expr,
+        };
+        ast.add(expr, Position::default(), Position::default())
+    }
+
+    fn nodes(rng: &mut Rng, ast: &mut Ast, depth: u32) -> Box<[NodeId]> {
+        (0..rng.below(3)).map(|_| tree(rng, ast, depth)).collect()
+    }
+
+    /// A random tree of at most `depth` levels of nodes above its literals
+    fn tree(rng: &mut Rng, ast: &mut Ast, depth: u32) -> NodeId {
+        if depth == 0 {
+            return literal(rng, ast, false);
+        }
+        let d = depth - 1;
+        let expr = match rng.below(17) {
+            0 => return literal(rng, ast, false),
+            1 => Expr::Variable("f".into()),
+            2 => Expr::Unary {
+                op: rng.pick(&UNARY_OPS),
+                e1: tree(rng, ast, d),
+            },
+            3 | 4 => Expr::Binary {
+                e1: tree(rng, ast, d),
+                op: rng.pick(&BINARY_OPS),
+                e2: tree(rng, ast, d),
+            },
+            5 => Expr::Conditional {
+                cond: tree(rng, ast, d),
+                e1: tree(rng, ast, d),
+                e2: tree(rng, ast, d),
+            },
+            6 => Expr::Comma {
+                e1: tree(rng, ast, d),
+                e2: tree(rng, ast, d),
+            },
+            7 => Expr::List(nodes(rng, ast, d)),
+            8 => Expr::Map(
+                (0..rng.below(3))
+                    .map(|_| (tree(rng, ast, d), tree(rng, ast, d)))
+                    .collect(),
+            ),
+            9 => Expr::Index {
+                e1: tree(rng, ast, d),
+                index: tree(rng, ast, d),
+            },
+            10 => Expr::Slice {
+                e1: tree(rng, ast, d),
+                from: (rng.below(2) == 0).then(|| tree(rng, ast, d)),
+                to: (rng.below(2) == 0).then(|| tree(rng, ast, d)),
+            },
+            11 => Expr::MethodCall(Box::new(MethodCall {
+                receiver: tree(rng, ast, d),
+                method: "push".into(),
+                args: nodes(rng, ast, d),
+            })),
+            12 => Expr::Call(Box::new(Call {
+                callee: tree(rng, ast, d),
+                args: nodes(rng, ast, d),
+            })),
+            13 => {
+                let target = match rng.below(2) {
+                    0 => Expr::Variable("x".into()),
+                    _ => Expr::Index {
+                        e1: tree(rng, ast, d),
+                        index: tree(rng, ast, d),
+                    },
+                };
+                let target = ast.add(target, Position::default(), Position::default());
+                let op = rng.pick(&ASSIGN_OPS);
+                let value = match op {
+                    AssignOp::PreStep(_) | AssignOp::PostStep(_) => None,
+                    _ => Some(tree(rng, ast, d)),
+                };
+                Expr::Assign { target, op, value }
+            }
+            14 => Expr::Function(Box::new(function(rng, ast, d, None))),
+            _ => {
+                let subject = tree(rng, ast, d);
+                let arms = (0..rng.below(3))
+                    .map(|_| {
+                        let patterns = (0..1 + rng.below(2))
+                            .map(|_| match rng.below(4) {
+                                0 => Pattern::Wildcard,
+                                1 => {
+                                    let class = Expr::Variable("C".into());
+                                    Pattern::Is(ast.add(
+                                        class,
+                                        Position::default(),
+                                        Position::default(),
+                                    ))
+                                }
+                                _ => Pattern::Literal(literal(rng, ast, true)),
+                            })
+                            .collect();
+                        let guard = (rng.below(2) == 0).then(|| tree(rng, ast, d));
+                        MatchArm {
+                            patterns,
+                            guard,
+                            body: tree(rng, ast, d),
+                            start: Position::default(),
+                            end: Position::default(),
+                        }
+                    })
+                    .collect();
+                Expr::Match { subject, arms }
+            }
+        };
+        ast.add(expr, Position::default(), Position::default())
+    }
+
+    /// Where a statement is, which decides the jumps it can make
+    #[derive(Clone, Copy)]
+    struct Context {
+        function: bool,
+        looping: bool,
+    }
+
+    /// A function whose body has at most `depth` levels of statements and nodes
+    fn function(rng: &mut Rng, ast: &mut Ast, depth: u32, name: Option<&str>) -> Function {
+        let context = Context {
+            function: true,
+            looping: false,
+        };
+        let body = Expr::Block(statements(rng, ast, depth, context));
+        Function {
+            name: name.map(Into::into),
+            params: rng
+                .pick(&[&[][..], &["a"], &["a", "b"]])
+                .iter()
+                .map(|&param| param.into())
+                .collect(),
+            body: ast.add(body, Position::default(), Position::default()),
+        }
+    }
+
+    fn statements(rng: &mut Rng, ast: &mut Ast, depth: u32, context: Context) -> Box<[NodeId]> {
+        if depth == 0 {
+            return Box::new([]);
+        }
+        (0..rng.below(3))
+            .map(|_| statement(rng, ast, depth - 1, context))
+            .collect()
+    }
+
+    fn var(rng: &mut Rng, ast: &mut Ast, depth: u32) -> Expr {
+        Expr::Var(Box::new(Var {
+            name: "x".into(),
+            init: (rng.below(2) == 0).then(|| tree(rng, ast, depth)),
+        }))
+    }
+
+    /// A random statement, which is only a `return` in the body of a function, and only a
+    /// `break` or a `continue` in a loop
+    fn statement(rng: &mut Rng, ast: &mut Ast, depth: u32, context: Context) -> NodeId {
+        let expr = match rng.below(8) {
+            0 | 1 => return tree(rng, ast, depth),
+            2 => var(rng, ast, depth),
+            _ if depth == 0 => return tree(rng, ast, depth),
+            3 => Expr::Block(statements(rng, ast, depth, context)),
+            4 => Expr::Function(Box::new(function(rng, ast, depth, Some("g")))),
+            5 => {
+                let cond = tree(rng, ast, depth - 1);
+                let then = statement(rng, ast, depth - 1, context);
+                // an `else` would go to an `if` without one ending the first branch
+                let otherwise = (rng.below(2) == 0 && !ends_with_lone_if(ast, then))
+                    .then(|| statement(rng, ast, depth - 1, context));
+                Expr::If {
+                    cond,
+                    then,
+                    otherwise,
+                }
+            }
+            6 => {
+                let cond = tree(rng, ast, depth - 1);
+                let looping = Context {
+                    looping: true,
+                    ..context
+                };
+                let body = statement(rng, ast, depth - 1, looping);
+                if rng.below(3) == 0 {
+                    // the condition is what the loop goes over
+                    let name = "x".into();
+                    return ast.add(
+                        Expr::ForIn(Box::new(ForIn {
+                            name,
+                            iterable: cond,
+                            body,
+                        })),
+                        Position::default(),
+                        Position::default(),
+                    );
+                }
+                let step = (rng.below(2) == 0).then(|| tree(rng, ast, depth - 1));
+                let looping = Expr::While { cond, body, step };
+                if step.is_none() || rng.below(2) == 0 {
+                    looping
+                } else {
+                    // a `for` loop with an initializer
+                    let looping = ast.add(looping, Position::default(), Position::default());
+                    let init = var(rng, ast, depth - 1);
+                    let init = ast.add(init, Position::default(), Position::default());
+                    Expr::Block(Box::new([init, looping]))
+                }
+            }
+            _ if context.function && rng.below(2) == 0 => {
+                Expr::Return((rng.below(2) == 0).then(|| tree(rng, ast, depth)))
+            }
+            _ if context.looping => match rng.below(2) {
+                0 => Expr::Break,
+                _ => Expr::Continue,
+            },
+            _ => return tree(rng, ast, depth),
+        };
+        ast.add(expr, Position::default(), Position::default())
+    }
+
+    /// A random program with at least one statement, as a lone expression is not a program
+    fn program(rng: &mut Rng, ast: &mut Ast, depth: u32) -> NodeId {
+        let statements = (0..1 + rng.below(3))
+            .map(|_| {
+                let context = Context {
+                    function: false,
+                    looping: false,
+                };
+                statement(rng, ast, depth, context)
+            })
+            .collect();
+        let value = (rng.below(2) == 0).then(|| tree(rng, ast, depth));
+        ast.add(
+            Expr::Program { statements, value },
+            Position::default(),
+            Position::default(),
+        )
+    }
+
+    /// Folds back what the printer writes for numbers without a literal: negated literals,
+    /// `(-9223372036854775807 - 1)` for i64::MIN and `(1e999 - 1e999)` for NaN
+    struct Normalizer;
+
+    impl VisitorMut for Normalizer {
+        type Error = Infallible;
+
+        fn visit_unary_mut(
+            &mut self,
+            ast: &mut Ast,
+            id: NodeId,
+            op: UnaryOp,
+            e1: NodeId,
+        ) -> Result<(), Infallible> {
+            walk_expr_mut(self, ast, id)?;
+            match (op, &ast[e1].expr) {
+                (UnaryOp::UnaMinus, Expr::Num(n)) if *n != i64::MIN => ast[id].expr = Expr::Num(-n),
+                (UnaryOp::UnaMinus, Expr::Float(x)) => ast[id].expr = Expr::Float(-x),
+                _ => (),
+            }
+            Ok(())
+        }
+
+        fn visit_binary_mut(
+            &mut self,
+            ast: &mut Ast,
+            id: NodeId,
+            e1: NodeId,
+            op: BinaryOp,
+            e2: NodeId,
+        ) -> Result<(), Infallible> {
+            walk_expr_mut(self, ast, id)?;
+            match (&ast[e1].expr, op, &ast[e2].expr) {
+                (Expr::Num(n), BinaryOp::BinMinus, Expr::Num(1)) if *n == i64::MIN + 1 => {
+                    ast[id].expr = Expr::Num(i64::MIN)
+                }
+                (Expr::Float(x), BinaryOp::BinMinus, Expr::Float(y))
+                    if *x == f64::INFINITY && *y == f64::INFINITY =>
+                {
+                    ast[id].expr = Expr::Float(f64::NAN)
+                }
+                _ => (),
+            }
+            Ok(())
+        }
+    }
+
+    fn normalized(mut ast: Ast) -> String {
+        let root = ast.root;
+        let Ok(()) = Normalizer.visit_expr_mut(&mut ast, root);
+        sexpr(&ast)
+    }
+
+    /// Printed source parses back into the tree it was printed from, up to the spelling of
+    /// numbers that have no literal
+    #[test]
+    fn source_parses_back_into_the_same_tree() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for i in 0..3000 {
+            let mut ast = Ast::default();
+            ast.root = match i % 2 {
+                0 => tree(&mut rng, &mut ast, 1 + i % 6),
+                _ => program(&mut rng, &mut ast, i % 6),
+            };
+            let printed = source(&ast);
+            assert_eq!(
+                normalized(parse(&printed).expect(&printed)),
+                normalized(ast),
+                "parsing {:?}",
+                printed
+            );
+        }
+    }
+}
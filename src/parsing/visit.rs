@@ -0,0 +1,872 @@
+use crate::parsing::expr::{
+    AssignOp, Ast, BinaryOp, Call, Expr, ForIn, Function, MatchArm, MethodCall, NodeId, Pattern,
+    UnaryOp, Var,
+};
+
+/// A pass over an expression tree.
+///
+/// `visit_expr` hands each node to the method for its kind, and every method defaults to
+/// visiting the children of its node, so a pass only overrides the nodes it cares about. An
+/// overriding method can still go down the tree with `walk_expr`. Errors stop the traversal;
+/// passes that cannot fail use `std::convert::Infallible`.
+pub trait Visitor {
+    type Error;
+
+    fn visit_expr(&mut self, ast: &Ast, id: NodeId) -> Result<(), Self::Error> {
+        match &ast[id].expr {
+            Expr::Num(_) | Expr::Float(_) | Expr::Str(_) | Expr::T | Expr::F | Expr::Nil => {
+                self.visit_literal(ast, id)
+            }
+            Expr::Variable(name) => self.visit_variable(ast, id, name),
+            Expr::Unary { op, e1 } => self.visit_unary(ast, id, *op, *e1),
+            Expr::Binary { e1, op, e2 } => self.visit_binary(ast, id, *e1, *op, *e2),
+            Expr::Conditional { cond, e1, e2 } => self.visit_conditional(ast, id, *cond, *e1, *e2),
+            Expr::Comma { e1, e2 } => self.visit_comma(ast, id, *e1, *e2),
+            Expr::Match { subject, arms } => self.visit_match(ast, id, *subject, arms),
+            Expr::List(elements) => self.visit_list(ast, id, elements),
+            Expr::Map(entries) => self.visit_map(ast, id, entries),
+            Expr::Index { e1, index } => self.visit_index(ast, id, *e1, *index),
+            Expr::Slice { e1, from, to } => self.visit_slice(ast, id, *e1, *from, *to),
+            Expr::MethodCall(call) => self.visit_method_call(ast, id, call),
+            Expr::Call(call) => self.visit_call(ast, id, call),
+            Expr::Assign { target, op, value } => self.visit_assign(ast, id, *target, *op, *value),
+            Expr::Function(function) => self.visit_function(ast, id, function),
+            Expr::Program { statements, value } => self.visit_program(ast, id, statements, *value),
+            Expr::Block(statements) => self.visit_block(ast, id, statements),
+            Expr::Var(var) => self.visit_var(ast, id, var),
+            Expr::Return(value) => self.visit_return(ast, id, *value),
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => self.visit_if(ast, id, *cond, *then, *otherwise),
+            Expr::While { cond, body, step } => self.visit_while(ast, id, *cond, *body, *step),
+            Expr::ForIn(for_in) => self.visit_for_in(ast, id, for_in),
+            Expr::Break => self.visit_break(ast, id),
+            Expr::Continue => self.visit_continue(ast, id),
+        }
+    }
+
+    /// Numbers, strings, booleans and nil
+    fn visit_literal(&mut self, _ast: &Ast, _id: NodeId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_variable(&mut self, _ast: &Ast, _id: NodeId, _name: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_unary(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _op: UnaryOp,
+        _e1: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_binary(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _op: BinaryOp,
+        _e2: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_conditional(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _cond: NodeId,
+        _e1: NodeId,
+        _e2: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_comma(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _e2: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_match(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _subject: NodeId,
+        _arms: &[MatchArm],
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_list(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _elements: &[NodeId],
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_map(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _entries: &[(NodeId, NodeId)],
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_index(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _index: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_slice(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _from: Option<NodeId>,
+        _to: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_method_call(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _call: &MethodCall,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_call(&mut self, ast: &Ast, id: NodeId, _call: &Call) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    /// The target is visited like any other child, so passes that evaluate it must override
+    /// this method to not read the element being assigned.
+    fn visit_assign(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _target: NodeId,
+        _op: AssignOp,
+        _value: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_function(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _function: &Function,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_program(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _statements: &[NodeId],
+        _value: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_block(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _statements: &[NodeId],
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_var(&mut self, ast: &Ast, id: NodeId, _var: &Var) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_return(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _value: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_if(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _cond: NodeId,
+        _then: NodeId,
+        _otherwise: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    /// The step, if any, is visited before the body, as it comes first in the source
+    fn visit_while(
+        &mut self,
+        ast: &Ast,
+        id: NodeId,
+        _cond: NodeId,
+        _body: NodeId,
+        _step: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_for_in(&mut self, ast: &Ast, id: NodeId, _for_in: &ForIn) -> Result<(), Self::Error> {
+        walk_expr(self, ast, id)
+    }
+
+    fn visit_break(&mut self, _ast: &Ast, _id: NodeId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_continue(&mut self, _ast: &Ast, _id: NodeId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_arm(&mut self, ast: &Ast, arm: &MatchArm) -> Result<(), Self::Error> {
+        walk_arm(self, ast, arm)
+    }
+
+    fn visit_pattern(&mut self, ast: &Ast, pattern: &Pattern) -> Result<(), Self::Error> {
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Literal(node) | Pattern::Is(node) => self.visit_expr(ast, *node),
+        }
+    }
+}
+
+/// Visits the children of an expression, in source order.
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, ast: &Ast, id: NodeId) -> Result<(), V::Error> {
+    match &ast[id].expr {
+        Expr::Num(_)
+        | Expr::Float(_)
+        | Expr::Str(_)
+        | Expr::T
+        | Expr::F
+        | Expr::Nil
+        | Expr::Variable(_)
+        | Expr::Break
+        | Expr::Continue => Ok(()),
+        Expr::Unary { e1, .. } => v.visit_expr(ast, *e1),
+        Expr::Binary { e1, e2, .. } | Expr::Comma { e1, e2 } => {
+            v.visit_expr(ast, *e1)?;
+            v.visit_expr(ast, *e2)
+        }
+        Expr::Conditional { cond, e1, e2 } => {
+            v.visit_expr(ast, *cond)?;
+            v.visit_expr(ast, *e1)?;
+            v.visit_expr(ast, *e2)
+        }
+        Expr::Match { subject, arms } => {
+            v.visit_expr(ast, *subject)?;
+            arms.iter().try_for_each(|arm| v.visit_arm(ast, arm))
+        }
+        Expr::List(elements) => elements.iter().try_for_each(|e| v.visit_expr(ast, *e)),
+        Expr::Map(entries) => entries.iter().try_for_each(|(key, value)| {
+            v.visit_expr(ast, *key)?;
+            v.visit_expr(ast, *value)
+        }),
+        Expr::Index { e1, index } => {
+            v.visit_expr(ast, *e1)?;
+            v.visit_expr(ast, *index)
+        }
+        Expr::Slice { e1, from, to } => {
+            v.visit_expr(ast, *e1)?;
+            from.iter()
+                .chain(to)
+                .try_for_each(|bound| v.visit_expr(ast, *bound))
+        }
+        Expr::MethodCall(call) => {
+            v.visit_expr(ast, call.receiver)?;
+            call.args.iter().try_for_each(|arg| v.visit_expr(ast, *arg))
+        }
+        Expr::Call(call) => {
+            v.visit_expr(ast, call.callee)?;
+            call.args.iter().try_for_each(|arg| v.visit_expr(ast, *arg))
+        }
+        Expr::Assign { target, value, .. } => {
+            v.visit_expr(ast, *target)?;
+            value.iter().try_for_each(|value| v.visit_expr(ast, *value))
+        }
+        Expr::Function(function) => v.visit_expr(ast, function.body),
+        Expr::Program { statements, value } => {
+            statements.iter().try_for_each(|s| v.visit_expr(ast, *s))?;
+            value.iter().try_for_each(|value| v.visit_expr(ast, *value))
+        }
+        Expr::Block(statements) => statements.iter().try_for_each(|s| v.visit_expr(ast, *s)),
+        Expr::Var(var) => var
+            .init
+            .iter()
+            .try_for_each(|init| v.visit_expr(ast, *init)),
+        Expr::Return(value) => value.iter().try_for_each(|value| v.visit_expr(ast, *value)),
+        Expr::If {
+            cond,
+            then,
+            otherwise,
+        } => {
+            v.visit_expr(ast, *cond)?;
+            v.visit_expr(ast, *then)?;
+            otherwise.iter().try_for_each(|e| v.visit_expr(ast, *e))
+        }
+        Expr::While { cond, body, step } => {
+            v.visit_expr(ast, *cond)?;
+            step.iter().try_for_each(|step| v.visit_expr(ast, *step))?;
+            v.visit_expr(ast, *body)
+        }
+        Expr::ForIn(for_in) => {
+            v.visit_expr(ast, for_in.iterable)?;
+            v.visit_expr(ast, for_in.body)
+        }
+    }
+}
+
+/// Visits the patterns, guard and body of a match arm.
+pub fn walk_arm<V: Visitor + ?Sized>(v: &mut V, ast: &Ast, arm: &MatchArm) -> Result<(), V::Error> {
+    for pattern in &arm.patterns {
+        v.visit_pattern(ast, pattern)?;
+    }
+    if let Some(guard) = arm.guard {
+        v.visit_expr(ast, guard)?;
+    }
+    v.visit_expr(ast, arm.body)
+}
+
+/// A pass that rewrites an expression tree in place, such as a desugaring or a constant
+/// folding.
+///
+/// Like `Visitor`, `visit_expr_mut` hands each node to the method for its kind, and every
+/// method defaults to visiting the children of its node. As the tree cannot stay borrowed
+/// while it is rewritten, methods get the ids and operators of their node but not its lists
+/// of children, which `walk_expr_mut` reads again at each step.
+pub trait VisitorMut {
+    type Error;
+
+    fn visit_expr_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Self::Error> {
+        match ast[id].expr {
+            Expr::Num(_) | Expr::Float(_) | Expr::Str(_) | Expr::T | Expr::F | Expr::Nil => {
+                self.visit_literal_mut(ast, id)
+            }
+            Expr::Variable(_) => self.visit_variable_mut(ast, id),
+            Expr::Unary { op, e1 } => self.visit_unary_mut(ast, id, op, e1),
+            Expr::Binary { e1, op, e2 } => self.visit_binary_mut(ast, id, e1, op, e2),
+            Expr::Conditional { cond, e1, e2 } => self.visit_conditional_mut(ast, id, cond, e1, e2),
+            Expr::Comma { e1, e2 } => self.visit_comma_mut(ast, id, e1, e2),
+            Expr::Match { subject, .. } => self.visit_match_mut(ast, id, subject),
+            Expr::List(_) => self.visit_list_mut(ast, id),
+            Expr::Map(_) => self.visit_map_mut(ast, id),
+            Expr::Index { e1, index } => self.visit_index_mut(ast, id, e1, index),
+            Expr::Slice { e1, from, to } => self.visit_slice_mut(ast, id, e1, from, to),
+            Expr::MethodCall(ref call) => {
+                let receiver = call.receiver;
+                self.visit_method_call_mut(ast, id, receiver)
+            }
+            Expr::Call(ref call) => {
+                let callee = call.callee;
+                self.visit_call_mut(ast, id, callee)
+            }
+            Expr::Assign { target, op, value } => self.visit_assign_mut(ast, id, target, op, value),
+            Expr::Function(ref function) => {
+                let body = function.body;
+                self.visit_function_mut(ast, id, body)
+            }
+            Expr::Program { .. } => self.visit_program_mut(ast, id),
+            Expr::Block(_) => self.visit_block_mut(ast, id),
+            Expr::Var(_) => self.visit_var_mut(ast, id),
+            Expr::Return(value) => self.visit_return_mut(ast, id, value),
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => self.visit_if_mut(ast, id, cond, then, otherwise),
+            Expr::While { cond, body, step } => self.visit_while_mut(ast, id, cond, body, step),
+            Expr::ForIn(_) => self.visit_for_in_mut(ast, id),
+            Expr::Break => self.visit_break_mut(ast, id),
+            Expr::Continue => self.visit_continue_mut(ast, id),
+        }
+    }
+
+    /// Numbers, strings, booleans and nil
+    fn visit_literal_mut(&mut self, _ast: &mut Ast, _id: NodeId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_variable_mut(&mut self, _ast: &mut Ast, _id: NodeId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_unary_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _op: UnaryOp,
+        _e1: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_binary_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _op: BinaryOp,
+        _e2: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_conditional_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _cond: NodeId,
+        _e1: NodeId,
+        _e2: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_comma_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _e2: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_match_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _subject: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_list_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_map_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_index_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _index: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_slice_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _e1: NodeId,
+        _from: Option<NodeId>,
+        _to: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_method_call_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _receiver: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_call_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _callee: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    /// The target is visited like any other child, so passes that rewrite it must keep it an
+    /// `Expr::Variable` or an `Expr::Index`.
+    fn visit_assign_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _target: NodeId,
+        _op: AssignOp,
+        _value: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_function_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _body: NodeId,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_program_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_block_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_var_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_return_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _value: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_if_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _cond: NodeId,
+        _then: NodeId,
+        _otherwise: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    /// The step, if any, is visited before the body, as it comes first in the source
+    fn visit_while_mut(
+        &mut self,
+        ast: &mut Ast,
+        id: NodeId,
+        _cond: NodeId,
+        _body: NodeId,
+        _step: Option<NodeId>,
+    ) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_for_in_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Self::Error> {
+        walk_expr_mut(self, ast, id)
+    }
+
+    fn visit_break_mut(&mut self, _ast: &mut Ast, _id: NodeId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn visit_continue_mut(&mut self, _ast: &mut Ast, _id: NodeId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// The `arm`-th arm of the match expression `id`. Passes that rewrite the name in a class
+    /// pattern must keep it an `Expr::Variable`.
+    fn visit_arm_mut(&mut self, ast: &mut Ast, id: NodeId, arm: usize) -> Result<(), Self::Error> {
+        walk_arm_mut(self, ast, id, arm)
+    }
+}
+
+/// Visits the children of an expression, in source order.
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    ast: &mut Ast,
+    id: NodeId,
+) -> Result<(), V::Error> {
+    match ast[id].expr {
+        Expr::Num(_)
+        | Expr::Float(_)
+        | Expr::Str(_)
+        | Expr::T
+        | Expr::F
+        | Expr::Nil
+        | Expr::Variable(_)
+        | Expr::Break
+        | Expr::Continue => Ok(()),
+        Expr::Unary { e1, .. } => v.visit_expr_mut(ast, e1),
+        Expr::Binary { e1, e2, .. } | Expr::Comma { e1, e2 } | Expr::Index { e1, index: e2 } => {
+            v.visit_expr_mut(ast, e1)?;
+            v.visit_expr_mut(ast, e2)
+        }
+        Expr::Conditional { cond, e1, e2 } => {
+            v.visit_expr_mut(ast, cond)?;
+            v.visit_expr_mut(ast, e1)?;
+            v.visit_expr_mut(ast, e2)
+        }
+        Expr::Slice { e1, from, to } => {
+            v.visit_expr_mut(ast, e1)?;
+            from.into_iter()
+                .chain(to)
+                .try_for_each(|bound| v.visit_expr_mut(ast, bound))
+        }
+        Expr::Assign { target, value, .. } => {
+            v.visit_expr_mut(ast, target)?;
+            value
+                .into_iter()
+                .try_for_each(|value| v.visit_expr_mut(ast, value))
+        }
+        Expr::Match { subject, ref arms } => {
+            let arm_count = arms.len();
+            v.visit_expr_mut(ast, subject)?;
+            (0..arm_count).try_for_each(|arm| v.visit_arm_mut(ast, id, arm))
+        }
+        Expr::MethodCall(ref call) => {
+            let receiver = call.receiver;
+            v.visit_expr_mut(ast, receiver)?;
+            walk_elements_mut(v, ast, id)
+        }
+        Expr::Function(ref function) => {
+            let body = function.body;
+            v.visit_expr_mut(ast, body)
+        }
+        Expr::Var(ref var) => match var.init {
+            Some(init) => v.visit_expr_mut(ast, init),
+            None => Ok(()),
+        },
+        Expr::Return(value) => value
+            .into_iter()
+            .try_for_each(|value| v.visit_expr_mut(ast, value)),
+        Expr::If {
+            cond,
+            then,
+            otherwise,
+        } => {
+            v.visit_expr_mut(ast, cond)?;
+            v.visit_expr_mut(ast, then)?;
+            otherwise
+                .into_iter()
+                .try_for_each(|e| v.visit_expr_mut(ast, e))
+        }
+        Expr::While { cond, body, step } => {
+            v.visit_expr_mut(ast, cond)?;
+            step.into_iter()
+                .try_for_each(|step| v.visit_expr_mut(ast, step))?;
+            v.visit_expr_mut(ast, body)
+        }
+        Expr::ForIn(ref for_in) => {
+            let (iterable, body) = (for_in.iterable, for_in.body);
+            v.visit_expr_mut(ast, iterable)?;
+            v.visit_expr_mut(ast, body)
+        }
+        Expr::List(_) | Expr::Map(_) | Expr::Call(_) | Expr::Program { .. } | Expr::Block(_) => {
+            walk_elements_mut(v, ast, id)
+        }
+    }
+}
+
+/// Visits the elements of a list, the keys and values of a map, the callee and arguments of a
+/// call, or the statements of a block or a program followed by the value of the program. The
+/// node is read again before each child, as visiting one may rewrite the tree.
+fn walk_elements_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    ast: &mut Ast,
+    id: NodeId,
+) -> Result<(), V::Error> {
+    let element = |ast: &Ast, i: usize| match &ast[id].expr {
+        Expr::List(elements) => elements.get(i).copied(),
+        Expr::Map(entries) => entries.get(i / 2).map(|&(key, value)| [key, value][i % 2]),
+        Expr::MethodCall(call) => call.args.get(i).copied(),
+        Expr::Call(call) => match i {
+            0 => Some(call.callee),
+            _ => call.args.get(i - 1).copied(),
+        },
+        Expr::Program { statements, value } => match i.checked_sub(statements.len()) {
+            None => Some(statements[i]),
+            Some(0) => *value,
+            Some(_) => None,
+        },
+        Expr::Block(statements) => statements.get(i).copied(),
+        _ => None,
+    };
+    let mut i = 0;
+    while let Some(child) = element(ast, i) {
+        v.visit_expr_mut(ast, child)?;
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Visits the patterns, guard and body of the `arm`-th arm of the match expression `id`.
+pub fn walk_arm_mut<V: VisitorMut + ?Sized>(
+    v: &mut V,
+    ast: &mut Ast,
+    id: NodeId,
+    arm: usize,
+) -> Result<(), V::Error> {
+    let child = |ast: &Ast, i: usize| match &ast[id].expr {
+        Expr::Match { arms, .. } => {
+            let arm = &arms[arm];
+            let patterns = arm.patterns.iter().filter_map(|pattern| match pattern {
+                Pattern::Wildcard => None,
+                Pattern::Literal(node) | Pattern::Is(node) => Some(*node),
+            });
+            patterns.chain(arm.guard).chain([arm.body]).nth(i)
+        }
+        _ => unreachable!("arms are only visited on match expressions"),
+    };
+    let mut i = 0;
+    while let Some(child) = child(ast, i) {
+        v.visit_expr_mut(ast, child)?;
+        i += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+    use crate::parsing::{parser::parse, printer};
+
+    /// Replaces integer arithmetic on literals by its result, unless it overflows
+    struct ConstantFolder;
+
+    impl VisitorMut for ConstantFolder {
+        type Error = Infallible;
+
+        fn visit_unary_mut(
+            &mut self,
+            ast: &mut Ast,
+            id: NodeId,
+            op: UnaryOp,
+            e1: NodeId,
+        ) -> Result<(), Infallible> {
+            walk_expr_mut(self, ast, id)?;
+            if let (UnaryOp::UnaMinus, Expr::Num(n)) = (op, &ast[e1].expr) {
+                if let Some(n) = n.checked_neg() {
+                    ast[id].expr = Expr::Num(n);
+                }
+            }
+            Ok(())
+        }
+
+        fn visit_binary_mut(
+            &mut self,
+            ast: &mut Ast,
+            id: NodeId,
+            e1: NodeId,
+            op: BinaryOp,
+            e2: NodeId,
+        ) -> Result<(), Infallible> {
+            walk_expr_mut(self, ast, id)?;
+            if let (Expr::Num(n1), Expr::Num(n2)) = (&ast[e1].expr, &ast[e2].expr) {
+                let folded = match op {
+                    BinaryOp::Plus => n1.checked_add(*n2),
+                    BinaryOp::BinMinus => n1.checked_sub(*n2),
+                    BinaryOp::Times => n1.checked_mul(*n2),
+                    _ => None,
+                };
+                if let Some(n) = folded {
+                    ast[id].expr = Expr::Num(n);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn constant_folding_rewrites_every_kind_of_node() {
+        for (src, folded) in [
+            ("(1 + 2) * -3", "-9"),
+            ("1 + x * 2 - 3", "(- (+ 1 (* x 2)) 3)"),
+            ("9223372036854775807 + 1", "(+ 9223372036854775807 1)"),
+            (
+                "[1 + 1, {2 * 2: 3 - 3}][0 + 0]",
+                "(index (list 2 (map (4 0))) 0)",
+            ),
+            (
+                "f(1 + 1)[-1:2 * 2].len(3 + 3)",
+                "(.len (slice (call f 2) -1 4) 6)",
+            ),
+            (
+                "[1][0] += 2 * 3, x ? 1 + 1 : 2 + 2",
+                "(, (+= (index (list 1) 0) 6) (?: x 2 4))",
+            ),
+            (
+                "match 1 + 1 { 2 if 1 + 1 == 2 => 3 * 3, _ => 4 }",
+                "(match 2 (2 if (== 2 2) => 9) (_ => 4))",
+            ),
+        ] {
+            let mut ast = parse(src).expect(src);
+            let root = ast.root;
+            let Ok(()) = ConstantFolder.visit_expr_mut(&mut ast, root);
+            assert_eq!(printer::sexpr(&ast), folded, "folding {:?}", src);
+        }
+    }
+
+    /// Nodes in the order a pass reaches them
+    #[derive(Default)]
+    struct Order(Vec<NodeId>);
+
+    impl Visitor for Order {
+        type Error = Infallible;
+
+        fn visit_expr(&mut self, ast: &Ast, id: NodeId) -> Result<(), Infallible> {
+            self.0.push(id);
+            walk_expr(self, ast, id)
+        }
+    }
+
+    impl VisitorMut for Order {
+        type Error = Infallible;
+
+        fn visit_expr_mut(&mut self, ast: &mut Ast, id: NodeId) -> Result<(), Infallible> {
+            self.0.push(id);
+            walk_expr_mut(self, ast, id)
+        }
+    }
+
+    #[test]
+    fn mutable_walk_follows_source_order() {
+        for src in [
+            "-1 + 2 * 3 ? [4, {5: 6}] : (7, 8)",
+            "f(a, b)[1:].m(2, 3)[:x]",
+            "match [1][0] -= 2 { -1 | 2 => 3, 4 if 5 => 6, _ => [7][0]++ }",
+        ] {
+            let mut ast = parse(src).expect(src);
+            let (mut by_ref, mut by_mut) = (Order::default(), Order::default());
+            let Ok(()) = by_ref.visit_expr(&ast, ast.root);
+            let root = ast.root;
+            let Ok(()) = by_mut.visit_expr_mut(&mut ast, root);
+            assert_eq!(by_mut.0, by_ref.0, "walking {:?}", src);
+            assert_eq!(by_mut.0.len(), ast.len(), "walking {:?}", src);
+        }
+    }
+}
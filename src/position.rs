@@ -1,26 +1,34 @@
 use std::fmt::Display;
 
-#[derive(Clone, Copy, Debug)]
+/// Line and column in the source, both starting at 1. Positions are ordered by where they
+/// appear in the source.
+///
+/// They are 32 bits wide to keep tokens and tree nodes small. Past 2^32 - 1 lines or columns,
+/// they stay at that maximum.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Position {
-    pub line: u64,
-    pub col: u64,
+    pub line: u32,
+    pub col: u32,
 }
 
-impl Position {
-    pub fn default() -> Position {
+impl Default for Position {
+    fn default() -> Position {
         Position { line: 1, col: 1 }
     }
+}
 
-    pub fn bump(self, n: u64) -> Position {
+impl Position {
+    pub fn bump(self, n: u32) -> Position {
         Position {
             line: self.line,
-            col: self.col + n,
+            col: self.col.saturating_add(n),
         }
     }
 
     pub fn brk(self) -> Position {
         Position {
-            line: self.line + 1,
+            line: self.line.saturating_add(1),
             col: 1,
         }
     }